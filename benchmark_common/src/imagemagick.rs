@@ -0,0 +1,136 @@
+use tokio::process::Command;
+
+use crate::tools;
+
+/// ImageMagickで読み書きできる必要のあるフォーマット
+#[derive(Clone, Copy)]
+pub struct Requirement {
+    /// `-list format` での名前（例: `WEBP`）
+    pub coder: &'static str,
+    pub read: bool,
+    pub write: bool,
+}
+
+/// ImageMagickのコマンドのパス（ImageMagick 7 の `magick`、なければ ImageMagick 6 の `convert`）
+pub fn program() -> &'static str {
+    tools::path("imagemagick")
+}
+
+/// ImageMagickを別のプロセスグループで起動する `Command` を作る（引数は `convert` と同じ）
+pub fn command() -> Command {
+    tools::command("imagemagick")
+}
+
+/// ImageMagickがインストールされ、`requirements` のフォーマットを読み書きできるか確認する
+///
+/// 画像を生成する前に呼ぶ。問題があれば対処方法を表示してエラーを返す。
+pub fn preflight(requirements: &[Requirement]) -> Result<(), Box<dyn std::error::Error>> {
+    let program = program();
+    let Some(version) = version(program) else {
+        println!("エラー: ImageMagick（magick または convert コマンド）が見つかりません");
+        println!("PATHにない場合は `--tool imagemagick=<パス>` または環境変数 BENCH_TOOL_IMAGEMAGICK で指定してください。");
+        print_install_help();
+        return Err("ImageMagickが見つかりません".into());
+    };
+    println!("ImageMagick: {} ({})", version, program);
+
+    let formats = list_formats(program);
+    let mut problems = Vec::new();
+    for requirement in merge(requirements) {
+        let mode = formats.iter().find(|(coder, _)| *coder == requirement.coder).map(|(_, mode)| mode.as_bytes());
+        let readable = mode.is_some_and(|mode| mode[0] == b'r');
+        let writable = mode.is_some_and(|mode| mode[1] == b'w');
+        let operation = match (requirement.read && !readable, requirement.write && !writable) {
+            (true, true) => "読み込み・書き込み",
+            (true, false) => "読み込み",
+            (false, true) => "書き込み",
+            (false, false) => continue,
+        };
+        problems.push((requirement.coder, operation));
+    }
+
+    if !problems.is_empty() {
+        println!("エラー: インストールされているImageMagickは、次のフォーマットに対応していません:");
+        for (coder, operation) in &problems {
+            println!("  {}の{}: {}", coder, operation, library(coder));
+        }
+        println!("\n`{} -list format` で対応しているフォーマット（Mode列の r: 読み込み、w: 書き込み）を確認できます。", program);
+        println!("必要なライブラリに対応したImageMagickを入れ直すか、対応しているフォーマットのみで比較してください。");
+        print_install_help();
+        return Err("ImageMagickが必要なフォーマットに対応していません".into());
+    }
+    Ok(())
+}
+
+/// ImageMagickが `coder`（`-list format` での名前）のフォーマットを読み書きできるか
+pub fn supports(coder: &str) -> bool {
+    list_formats(program()).iter().any(|(name, mode)| name == coder && mode.starts_with("rw"))
+}
+
+/// `-version` の出力がImageMagickであれば、そのバージョン（例: `ImageMagick 7.1.1-29 Q16-HDRI x86_64`）
+///
+/// Windowsにはファイルシステムを変換する同名の `convert` があるため、出力の内容で見分ける。
+fn version(program: &str) -> Option<String> {
+    let output = std::process::Command::new(program).arg("-version").output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let line = text.lines().find(|line| line.contains("ImageMagick"))?;
+    Some(line.trim().trim_start_matches("Version:").trim().to_string())
+}
+
+/// `-list format` のフォーマット名と Mode（`rw+` のような読み込み・書き込み・複数画像への対応）の一覧
+fn list_formats(program: &str) -> Vec<(String, String)> {
+    let Ok(output) = std::process::Command::new(program).args(["-list", "format"]).output() else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            // `WEBP* WEBP rw+ WebP Image Format (libwebp 1.2.4)` のように、名前のあとにモジュール名と Mode が続く
+            let mut fields = line.split_whitespace();
+            let coder = fields.next()?.trim_end_matches('*');
+            let mode = fields.take(2).find(|field| is_mode(field))?;
+            Some((coder.to_string(), mode.to_string()))
+        })
+        .collect()
+}
+
+fn is_mode(field: &str) -> bool {
+    let bytes = field.as_bytes();
+    bytes.len() == 3 && matches!(bytes[0], b'r' | b'-') && matches!(bytes[1], b'w' | b'-') && matches!(bytes[2], b'+' | b'-')
+}
+
+/// 同じフォーマットの要件をまとめる
+fn merge(requirements: &[Requirement]) -> Vec<Requirement> {
+    let mut merged: Vec<Requirement> = Vec::new();
+    for requirement in requirements {
+        match merged.iter_mut().find(|merged| merged.coder == requirement.coder) {
+            Some(merged) => {
+                merged.read |= requirement.read;
+                merged.write |= requirement.write;
+            },
+            None => merged.push(*requirement),
+        }
+    }
+    merged
+}
+
+/// フォーマットの読み書きに必要なライブラリ
+fn library(coder: &str) -> &'static str {
+    match coder {
+        "PNG" => "libpng が必要です",
+        "JPEG" => "libjpeg が必要です",
+        "WEBP" => "libwebp が必要です",
+        "AVIF" | "HEIC" => "libheif が必要です。AVIFにはAV1のエンコーダーも必要です",
+        "JXL" => "libjxl が必要です",
+        "QOI" => "ImageMagick 7.1以降が必要です",
+        _ => "対応したImageMagickが必要です",
+    }
+}
+
+fn print_install_help() {
+    println!("\nUbuntu/Debianでのインストール:");
+    println!("  sudo apt-get install imagemagick");
+    println!("  (配布パッケージがAVIF・JPEG XLに対応していない場合は、新しいImageMagickをソースからビルドしてください)");
+    println!("\nmacOSでのインストール:");
+    println!("  brew install imagemagick");
+}
@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tokio::process::Command;
+
+/// Ctrl-C で中断した場合の終了コード（128 + SIGINT）
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Ctrl-C を受け取ったら中断フラグを立てる
+///
+/// 各処理はフラグを見て新しい作業の開始をやめ、結果の保存とクリーンアップを行ってから終了する。
+/// 2回目の Ctrl-C ではその場で終了する。
+pub fn install() -> Result<(), Box<dyn std::error::Error>> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            eprintln!("\n強制終了します（作業ファイルが残っている可能性があります）");
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+        eprintln!("\n中断を受け付けました。実行中の処理が終わり次第、結果を保存して終了します（もう一度 Ctrl-C で強制終了）");
+    })?;
+    Ok(())
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// 外部コマンドを別のプロセスグループで起動する `Command` を作る
+///
+/// 端末の Ctrl-C が子プロセスに直接届くと実行中の変換が失敗扱いになるため、
+/// 子プロセスには処理を最後まで終えさせ、中断は本体側のフラグで扱う。
+pub fn command(program: &str) -> Command {
+    let mut command = Command::new(program);
+    #[cfg(unix)]
+    command.process_group(0);
+    command
+}
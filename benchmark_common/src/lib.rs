@@ -0,0 +1,10 @@
+//! 各ベンチマークで共通して使う処理
+//!
+//! 結果ファイルの出力形式や実行環境の記録、外部コマンドの扱いをベンチマーク間で揃えるため、各クレートはこのクレートの実装を使う。
+
+pub mod imagemagick;
+pub mod interrupt;
+pub mod manifest;
+pub mod output;
+pub mod pool;
+pub mod tools;
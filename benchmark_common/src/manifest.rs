@@ -0,0 +1,149 @@
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs;
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::tools::ResolvedTool;
+
+/// 実行環境の情報（結果ファイルの `run_id` 列から参照される）
+#[derive(Serialize, Deserialize)]
+pub struct RunManifest {
+    pub run_id: String,
+    pub started_at: String,
+    pub program: String,
+    pub arguments: Vec<String>,
+    pub results_file: String,
+    pub hostname: String,
+    pub os: String,
+    pub kernel: String,
+    pub cpu_model: String,
+    pub cpu_cores: usize,
+    pub target_path: String,
+    pub filesystem_type: String,
+    /// 使用した外部コマンドの絶対パスとバージョン
+    pub tools: Vec<ResolvedTool>,
+}
+
+impl RunManifest {
+    pub fn collect(results_file: &Path, target_path: &Path, tools: &[ResolvedTool]) -> Self {
+        let now = Utc::now();
+        let args: Vec<String> = env::args().collect();
+
+        RunManifest {
+            run_id: generate_run_id(),
+            started_at: now.to_rfc3339(),
+            program: args.first().cloned().unwrap_or_default(),
+            arguments: args.iter().skip(1).cloned().collect(),
+            results_file: results_file.display().to_string(),
+            hostname: hostname(),
+            os: format!("{} {}", env::consts::OS, env::consts::ARCH),
+            kernel: command_line("uname", &["-sr"], "").unwrap_or_else(|| "unknown".to_string()),
+            cpu_model: cpu_model(),
+            cpu_cores: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            target_path: target_path.display().to_string(),
+            filesystem_type: filesystem_type(target_path),
+            tools: tools.to_vec(),
+        }
+    }
+
+    /// 結果ファイルと同じ場所に `<結果ファイル名>.manifest.json` として書き出す
+    ///
+    /// 追記モードでは既存の実行環境の一覧に今回の分を加える。
+    pub fn write_next_to(&self, results_file: &Path, append: bool) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let path = manifest_path(results_file);
+
+        let mut manifests: Vec<serde_json::Value> = if append && path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            Vec::new()
+        };
+        manifests.push(serde_json::to_value(self)?);
+
+        fs::write(&path, serde_json::to_string_pretty(&manifests)? + "\n")?;
+        Ok(path)
+    }
+}
+
+pub fn manifest_path(results_file: &Path) -> PathBuf {
+    let mut path = results_file.as_os_str().to_owned();
+    path.push(".manifest.json");
+    PathBuf::from(path)
+}
+
+/// `20261018T120000Z-1a2b3c4d` のような、時刻順に並び他のマシンとも衝突しにくいID
+fn generate_run_id() -> String {
+    let now = Utc::now();
+    let random = RandomState::new().hash_one(now.timestamp_nanos_opt().unwrap_or_default()) as u32;
+    format!("{}-{:08x}", now.format("%Y%m%dT%H%M%SZ"), random)
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .or_else(|| command_line("hostname", &[], ""))
+        .or_else(|| env::var("HOSTNAME").ok())
+        .or_else(|| env::var("COMPUTERNAME").ok())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn cpu_model() -> String {
+    if let Ok(cpuinfo) = fs::read_to_string("/proc/cpuinfo") {
+        let model = cpuinfo
+            .lines()
+            .find(|line| line.starts_with("model name") || line.starts_with("Model"))
+            .and_then(|line| line.split_once(':'))
+            .map(|(_, value)| value.trim().to_string());
+        if let Some(model) = model {
+            return model;
+        }
+    }
+
+    command_line("sysctl", &["-n", "machdep.cpu.brand_string"], "").unwrap_or_else(|| "unknown".to_string())
+}
+
+/// `/proc/mounts` から、パスを含む最も深いマウントポイントのファイルシステム種別を探す
+fn filesystem_type(path: &Path) -> String {
+    // 書き込み先がまだ作成されていない場合は、存在する親ディレクトリで判定する
+    let mut existing = path.to_path_buf();
+    while !existing.exists() {
+        if !existing.pop() {
+            break;
+        }
+    }
+    let Ok(path) = fs::canonicalize(if existing.as_os_str().is_empty() { Path::new(".") } else { &existing }) else {
+        return "unknown".to_string();
+    };
+
+    let Ok(mounts) = fs::read_to_string("/proc/mounts") else {
+        return "unknown".to_string();
+    };
+
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let mount_point = fields.nth(1)?.replace("\\040", " ");
+            let fs_type = fields.next()?;
+            path.starts_with(&mount_point).then(|| (mount_point.len(), fs_type.to_string()))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, fs_type)| fs_type)
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// コマンドの出力から `keyword` を含む最初の行（空なら最初の空でない行）を返す
+pub fn command_line(program: &str, args: &[&str], keyword: &str) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    let text = format!("{}\n{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && line.contains(keyword))
+        .map(str::to_string)
+}
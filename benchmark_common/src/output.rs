@@ -0,0 +1,883 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float64Type, Int64Type, UInt64Type};
+use arrow_array::{Array, ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray, UInt64Array};
+use arrow_schema::{DataType, Field, Schema};
+use csv::{Writer, WriterBuilder};
+use parquet::arrow::ArrowWriter;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde::Serialize;
+use serde::ser::{self, Impossible, SerializeStruct};
+use serde_json::Value;
+
+/// 結果ファイルの出力形式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    Json,
+    Ndjson,
+    Parquet,
+}
+
+impl OutputFormat {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" | "jsonl" => Ok(OutputFormat::Ndjson),
+            "parquet" => Ok(OutputFormat::Parquet),
+            _ => Err(format!("未対応の出力形式です: {} (csv, json, ndjson, parquet のいずれかを指定してください)", value)),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// 出力先が指定されていない場合のデフォルトパス（例: `xxx_results.csv`）
+pub fn default_path(stem: &str, format: OutputFormat) -> PathBuf {
+    PathBuf::from(format!("{}.{}", stem, format.extension()))
+}
+
+/// 結果ファイル `xxx.csv` に対する、画像ごとの結果ファイル `xxx_details.csv`
+pub fn details_path(results_file: &Path) -> PathBuf {
+    let stem = results_file.file_stem().unwrap_or_default().to_string_lossy();
+    match results_file.extension() {
+        Some(extension) => results_file.with_file_name(format!("{}_details.{}", stem, extension.to_string_lossy())),
+        None => results_file.with_file_name(format!("{}_details", stem)),
+    }
+}
+
+/// 結果の書き出し先
+///
+/// CSVとNDJSONは1行ずつ書き込み、JSONとParquetはファイル全体を書き直す必要があるため
+/// 行をメモリに保持し、`flush`のたびにファイルを作り直す。
+/// Parquetの列の型は値からではなく行の型のフィールドの型から決める（`ColumnTypes`）。
+///
+/// エラーで途中終了した場合もそれまでの行が失われないよう、書き出していない行はドロップ時に書き出す。
+pub enum ResultWriter {
+    Csv(Box<Writer<File>>),
+    Ndjson(BufWriter<File>),
+    /// `dirty` は最後の `flush` 以降に追加した行があるか
+    Json { path: PathBuf, rows: Vec<Value>, dirty: bool },
+    Parquet { path: PathBuf, rows: Vec<Value>, types: ColumnTypes, dirty: bool },
+}
+
+impl ResultWriter {
+    /// 結果ファイルを開く
+    ///
+    /// `append` が真で既存のファイルがある場合は、列構成が `T` と一致することを確認したうえで
+    /// 既存の行を残したまま追記する。列構成が異なる場合や、Parquetで列の型が異なる場合はエラーにする。
+    pub fn create<T: Serialize + Default>(path: &Path, format: OutputFormat, append: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let exists = fs::metadata(path).map(|m| m.len() > 0).unwrap_or(false);
+        if !append || !exists {
+            let writer = match format {
+                OutputFormat::Csv => ResultWriter::Csv(Box::new(Writer::from_path(path)?)),
+                OutputFormat::Ndjson => ResultWriter::Ndjson(BufWriter::new(File::create(path)?)),
+                OutputFormat::Json => ResultWriter::Json { path: path.to_path_buf(), rows: Vec::new(), dirty: false },
+                OutputFormat::Parquet => ResultWriter::Parquet {
+                    path: path.to_path_buf(),
+                    rows: Vec::new(),
+                    types: ColumnTypes::of(&T::default())?,
+                    dirty: false,
+                },
+            };
+            return Ok(writer);
+        }
+
+        let columns = column_names::<T>()?;
+        let writer = match format {
+            OutputFormat::Csv => {
+                let mut header = String::new();
+                BufReader::new(File::open(path)?).read_line(&mut header)?;
+                let existing: Vec<String> = header.trim_end().split(',').map(|name| name.trim_matches('"').to_string()).collect();
+                check_schema(path, &existing, &columns)?;
+
+                let file = OpenOptions::new().append(true).open(path)?;
+                ResultWriter::Csv(Box::new(WriterBuilder::new().has_headers(false).from_writer(file)))
+            },
+            OutputFormat::Ndjson => {
+                let mut first = String::new();
+                BufReader::new(File::open(path)?).read_line(&mut first)?;
+                check_schema(path, &object_keys(&serde_json::from_str(&first)?), &columns)?;
+
+                ResultWriter::Ndjson(BufWriter::new(OpenOptions::new().append(true).open(path)?))
+            },
+            OutputFormat::Json => {
+                let rows: Vec<Value> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+                if let Some(first) = rows.first() {
+                    check_schema(path, &object_keys(first), &columns)?;
+                }
+                ResultWriter::Json { path: path.to_path_buf(), rows, dirty: false }
+            },
+            OutputFormat::Parquet => {
+                let (rows, existing_types) = read_parquet(path)?;
+                if let Some(first) = rows.first() {
+                    check_schema(path, &object_keys(first), &columns)?;
+                }
+                let mut types = ColumnTypes::of(&T::default())?;
+                types.merge(&existing_types).map_err(|e| format!("{} に追記できません: {}", path.display(), e))?;
+                ResultWriter::Parquet { path: path.to_path_buf(), rows, types, dirty: false }
+            },
+        };
+        Ok(writer)
+    }
+
+    pub fn serialize<T: Serialize>(&mut self, row: T) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            ResultWriter::Csv(writer) => writer.serialize(row)?,
+            ResultWriter::Ndjson(writer) => {
+                serde_json::to_writer(&mut *writer, &row)?;
+                writer.write_all(b"\n")?;
+            },
+            ResultWriter::Json { rows, dirty, .. } => {
+                rows.push(serde_json::to_value(&row)?);
+                *dirty = true;
+            },
+            ResultWriter::Parquet { rows, types, dirty, .. } => {
+                types.merge(&ColumnTypes::of(&row)?)?;
+                rows.push(serde_json::to_value(&row)?);
+                *dirty = true;
+            },
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            ResultWriter::Csv(writer) => writer.flush()?,
+            ResultWriter::Ndjson(writer) => writer.flush()?,
+            ResultWriter::Json { path, rows, dirty } => {
+                let mut writer = BufWriter::new(File::create(&*path)?);
+                serde_json::to_writer_pretty(&mut writer, rows)?;
+                writer.write_all(b"\n")?;
+                writer.flush()?;
+                *dirty = false;
+            },
+            ResultWriter::Parquet { path, rows, types, dirty } => {
+                write_parquet(path, rows, types)?;
+                *dirty = false;
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ResultWriter {
+    fn drop(&mut self) {
+        // CSVとNDJSONはバッファのドロップ時に書き出される
+        let dirty = match self {
+            ResultWriter::Json { dirty, .. } | ResultWriter::Parquet { dirty, .. } => *dirty,
+            ResultWriter::Csv(_) | ResultWriter::Ndjson(_) => false,
+        };
+        if dirty && let Err(e) = self.flush() {
+            eprintln!("結果ファイルの書き出しに失敗しました: {}", e);
+        }
+    }
+}
+
+/// 既存の結果ファイルから、`keep` が偽を返す行を取り除く（取り除いた行数を返す）
+///
+/// `--resume` で、結果を書き込んだあとチェックポイントに記録される前に中断した行を消すのに使う。
+/// `keep` には列名から値を文字列で返す関数が渡される（欠損値は空文字列）。
+/// 途中で失敗しても元のファイルが壊れないよう、一時ファイルに書いてから置き換える。
+pub fn retain_rows(
+    path: &Path,
+    format: OutputFormat,
+    keep: impl Fn(&dyn Fn(&str) -> Option<String>) -> bool,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    if !fs::metadata(path).map(|m| m.len() > 0).unwrap_or(false) {
+        return Ok(0);
+    }
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let keep_value = |row: &Value| keep(&|column| row.get(column).map(value_text));
+    let removed = match format {
+        OutputFormat::Csv => {
+            let mut reader = csv::Reader::from_path(path)?;
+            let headers = reader.headers()?.clone();
+            let mut writer = Writer::from_path(&temp_path)?;
+            writer.write_record(&headers)?;
+            let mut removed = 0;
+            for record in reader.records() {
+                let record = record?;
+                let column = |name: &str| headers.iter().position(|header| header == name).and_then(|i| record.get(i)).map(str::to_string);
+                if keep(&column) {
+                    writer.write_record(&record)?;
+                } else {
+                    removed += 1;
+                }
+            }
+            writer.flush()?;
+            removed
+        },
+        OutputFormat::Ndjson => {
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            let mut removed = 0;
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if keep_value(&serde_json::from_str(&line)?) {
+                    writeln!(writer, "{}", line)?;
+                } else {
+                    removed += 1;
+                }
+            }
+            writer.flush()?;
+            removed
+        },
+        OutputFormat::Json => {
+            let mut rows: Vec<Value> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+            let count = rows.len();
+            rows.retain(keep_value);
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            serde_json::to_writer_pretty(&mut writer, &rows)?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+            count - rows.len()
+        },
+        OutputFormat::Parquet => {
+            let (mut rows, types) = read_parquet(path)?;
+            let count = rows.len();
+            rows.retain(keep_value);
+            if rows.is_empty() {
+                // 行のないParquetは書き出さないため、ファイルごと削除する
+                fs::remove_file(path)?;
+                return Ok(count);
+            }
+            write_parquet(&temp_path, &rows, &types)?;
+            count - rows.len()
+        },
+    };
+
+    fs::rename(&temp_path, path)?;
+    Ok(removed)
+}
+
+/// `retain_rows` に渡す、JSON値の文字列表現（CSVのセルと同じ表記）
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// 行の型の列名（フィールドの宣言順）
+fn column_names<T: Serialize + Default>() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    Ok(object_keys(&serde_json::to_value(T::default())?))
+}
+
+fn object_keys(value: &Value) -> Vec<String> {
+    value.as_object().map(|map| map.keys().cloned().collect()).unwrap_or_default()
+}
+
+fn check_schema(path: &Path, existing: &[String], expected: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if existing != expected {
+        return Err(format!(
+            "{} の列構成が一致しないため追記できません (既存: {} / 今回: {})",
+            path.display(),
+            existing.join(","),
+            expected.join(",")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// 追記のために既存のParquetファイルを行単位のJSON値に戻す
+///
+/// 値が1つでもある列の型も返す（全て欠損値の列は型を決めない）。
+fn read_parquet(path: &Path) -> Result<(Vec<Value>, ColumnTypes), Box<dyn std::error::Error>> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()?;
+    let mut rows = Vec::new();
+    let mut types = ColumnTypes::default();
+
+    for batch in reader {
+        let batch = batch?;
+        let schema = batch.schema();
+        for (field, column) in schema.fields().iter().zip(batch.columns()) {
+            let data_type = (column.null_count() < column.len()).then(|| field.data_type().clone());
+            types.merge(&ColumnTypes(vec![(field.name().clone(), data_type)]))?;
+        }
+        for row in 0..batch.num_rows() {
+            let mut object = serde_json::Map::new();
+            for (field, column) in schema.fields().iter().zip(batch.columns()) {
+                object.insert(field.name().clone(), cell_value(column.as_ref(), row)?);
+            }
+            rows.push(Value::Object(object));
+        }
+    }
+
+    Ok((rows, types))
+}
+
+fn cell_value(column: &dyn Array, row: usize) -> Result<Value, Box<dyn std::error::Error>> {
+    if column.is_null(row) {
+        return Ok(Value::Null);
+    }
+    let value = match column.data_type() {
+        DataType::Boolean => Value::from(column.as_boolean().value(row)),
+        DataType::UInt64 => Value::from(column.as_primitive::<UInt64Type>().value(row)),
+        DataType::Int64 => Value::from(column.as_primitive::<Int64Type>().value(row)),
+        DataType::Float64 => Value::from(column.as_primitive::<Float64Type>().value(row)),
+        DataType::Utf8 => Value::from(column.as_string::<i32>().value(row)),
+        other => return Err(format!("未対応のParquet列の型です: {}", other).into()),
+    };
+    Ok(value)
+}
+
+fn write_parquet(path: &Path, rows: &[Value], types: &ColumnTypes) -> Result<(), Box<dyn std::error::Error>> {
+    // 行が無い場合は書き出さない
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let mut fields = Vec::new();
+    let mut arrays: Vec<ArrayRef> = Vec::new();
+    for (name, data_type) in &types.0 {
+        // 全ての行で欠損値の `Option` の列は型が決まらないため、文字列の列にする
+        let data_type = data_type.clone().unwrap_or(DataType::Utf8);
+        let values: Vec<&Value> = rows.iter().map(|row| row.get(name).unwrap_or(&Value::Null)).collect();
+        arrays.push(column_array(name, &data_type, &values)?);
+        fields.push(Field::new(name, data_type, true));
+    }
+
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(Arc::clone(&schema), arrays)?;
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+/// JSON値の列を、型 `data_type` のArrowの配列に変換する
+fn column_array(name: &str, data_type: &DataType, values: &[&Value]) -> Result<ArrayRef, Box<dyn std::error::Error>> {
+    let array: ArrayRef = match data_type {
+        DataType::Boolean => Arc::new(BooleanArray::from(typed_values(name, data_type, values, Value::as_bool)?)),
+        DataType::UInt64 => Arc::new(UInt64Array::from(typed_values(name, data_type, values, Value::as_u64)?)),
+        DataType::Int64 => Arc::new(Int64Array::from(typed_values(name, data_type, values, Value::as_i64)?)),
+        DataType::Float64 => Arc::new(Float64Array::from(typed_values(name, data_type, values, Value::as_f64)?)),
+        _ => Arc::new(StringArray::from(typed_values(name, data_type, values, |v| v.as_str().map(str::to_string))?)),
+    };
+    Ok(array)
+}
+
+/// 欠損値以外の値が全て `convert` で変換できることを確認して変換する
+fn typed_values<T>(
+    name: &str,
+    data_type: &DataType,
+    values: &[&Value],
+    convert: impl Fn(&Value) -> Option<T>,
+) -> Result<Vec<Option<T>>, Box<dyn std::error::Error>> {
+    values
+        .iter()
+        .map(|value| match value {
+            Value::Null => Ok(None),
+            value => convert(value).map(Some).ok_or_else(|| format!("列 {} の値 {} は {} に変換できません", name, value, data_type).into()),
+        })
+        .collect()
+}
+
+/// Parquetの列名と列の型（フィールドの宣言順）
+///
+/// 型は行の型のフィールドの型から決める。`None` の `Option` のように値から型が分からない列は `None` にしておき、
+/// 値のある行を書き込んだ時点で決める。
+#[derive(Default)]
+pub struct ColumnTypes(Vec<(String, Option<DataType>)>);
+
+impl ColumnTypes {
+    /// 構造体 `row` の各フィールドの型
+    fn of<T: Serialize>(row: &T) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(row.serialize(RowTypes)?)
+    }
+
+    /// `other` で型の分かった列を取り込む。同じ列の型が異なる場合はエラーにする。
+    fn merge(&mut self, other: &ColumnTypes) -> Result<(), String> {
+        for (name, data_type) in &other.0 {
+            match self.0.iter_mut().find(|(existing, _)| existing == name) {
+                Some((_, existing @ None)) => *existing = data_type.clone(),
+                Some((_, Some(existing))) => {
+                    if let Some(data_type) = data_type.as_ref().filter(|data_type| *data_type != existing) {
+                        return Err(format!("列 {} の型が一致しません (既存: {} / 今回: {})", name, existing, data_type));
+                    }
+                },
+                None => self.0.push((name.clone(), data_type.clone())),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct ColumnTypeError(String);
+
+impl fmt::Display for ColumnTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ColumnTypeError {}
+
+impl ser::Error for ColumnTypeError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        ColumnTypeError(message.to_string())
+    }
+}
+
+/// 行の構造体を `ColumnTypes` に変換する `Serializer`
+struct RowTypes;
+
+/// フィールドの値をParquetの列の型に変換する `Serializer`（`None` は型が分からない）
+struct FieldType;
+
+fn unsupported<T>(kind: &str) -> Result<T, ColumnTypeError> {
+    Err(ColumnTypeError(format!("結果の行に未対応の型が含まれています: {}", kind)))
+}
+
+impl SerializeStruct for ColumnTypes {
+    type Ok = ColumnTypes;
+    type Error = ColumnTypeError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> {
+        self.0.push((key.to_string(), value.serialize(FieldType)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self)
+    }
+}
+
+impl ser::Serializer for RowTypes {
+    type Ok = ColumnTypes;
+    type Error = ColumnTypeError;
+    type SerializeSeq = Impossible<ColumnTypes, ColumnTypeError>;
+    type SerializeTuple = Impossible<ColumnTypes, ColumnTypeError>;
+    type SerializeTupleStruct = Impossible<ColumnTypes, ColumnTypeError>;
+    type SerializeTupleVariant = Impossible<ColumnTypes, ColumnTypeError>;
+    type SerializeMap = Impossible<ColumnTypes, ColumnTypeError>;
+    type SerializeStruct = ColumnTypes;
+    type SerializeStructVariant = Impossible<ColumnTypes, ColumnTypeError>;
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Self::Error> {
+        Ok(ColumnTypes::default())
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<Self::Ok, Self::Error> { unsupported("bool") }
+    fn serialize_i8(self, _v: i8) -> Result<Self::Ok, Self::Error> { unsupported("i8") }
+    fn serialize_i16(self, _v: i16) -> Result<Self::Ok, Self::Error> { unsupported("i16") }
+    fn serialize_i32(self, _v: i32) -> Result<Self::Ok, Self::Error> { unsupported("i32") }
+    fn serialize_i64(self, _v: i64) -> Result<Self::Ok, Self::Error> { unsupported("i64") }
+    fn serialize_u8(self, _v: u8) -> Result<Self::Ok, Self::Error> { unsupported("u8") }
+    fn serialize_u16(self, _v: u16) -> Result<Self::Ok, Self::Error> { unsupported("u16") }
+    fn serialize_u32(self, _v: u32) -> Result<Self::Ok, Self::Error> { unsupported("u32") }
+    fn serialize_u64(self, _v: u64) -> Result<Self::Ok, Self::Error> { unsupported("u64") }
+    fn serialize_f32(self, _v: f32) -> Result<Self::Ok, Self::Error> { unsupported("f32") }
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Self::Error> { unsupported("f64") }
+    fn serialize_char(self, _v: char) -> Result<Self::Ok, Self::Error> { unsupported("char") }
+    fn serialize_str(self, _v: &str) -> Result<Self::Ok, Self::Error> { unsupported("str") }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Self::Error> { unsupported("bytes") }
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> { unsupported("Option") }
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<Self::Ok, Self::Error> { unsupported("Option") }
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> { unsupported("()") }
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok, Self::Error> { unsupported(name) }
+    fn serialize_unit_variant(self, name: &'static str, _index: u32, _variant: &'static str) -> Result<Self::Ok, Self::Error> {
+        unsupported(name)
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        unsupported(name)
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> { unsupported("seq") }
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> { unsupported("tuple") }
+    fn serialize_tuple_struct(self, name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        unsupported(name)
+    }
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        unsupported(name)
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> { unsupported("map") }
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        unsupported(name)
+    }
+}
+
+impl ser::Serializer for FieldType {
+    type Ok = Option<DataType>;
+    type Error = ColumnTypeError;
+    type SerializeSeq = Impossible<Option<DataType>, ColumnTypeError>;
+    type SerializeTuple = Impossible<Option<DataType>, ColumnTypeError>;
+    type SerializeTupleStruct = Impossible<Option<DataType>, ColumnTypeError>;
+    type SerializeTupleVariant = Impossible<Option<DataType>, ColumnTypeError>;
+    type SerializeMap = Impossible<Option<DataType>, ColumnTypeError>;
+    type SerializeStruct = Impossible<Option<DataType>, ColumnTypeError>;
+    type SerializeStructVariant = Impossible<Option<DataType>, ColumnTypeError>;
+
+    fn serialize_bool(self, _v: bool) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::Boolean)) }
+    fn serialize_i8(self, _v: i8) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::Int64)) }
+    fn serialize_i16(self, _v: i16) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::Int64)) }
+    fn serialize_i32(self, _v: i32) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::Int64)) }
+    fn serialize_i64(self, _v: i64) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::Int64)) }
+    fn serialize_u8(self, _v: u8) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::UInt64)) }
+    fn serialize_u16(self, _v: u16) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::UInt64)) }
+    fn serialize_u32(self, _v: u32) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::UInt64)) }
+    fn serialize_u64(self, _v: u64) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::UInt64)) }
+    // u128 の値はJSONでも u64 の範囲でしか表せないため、u64 の列にする
+    fn serialize_u128(self, _v: u128) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::UInt64)) }
+    fn serialize_f32(self, _v: f32) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::Float64)) }
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::Float64)) }
+    fn serialize_char(self, _v: char) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::Utf8)) }
+    fn serialize_str(self, _v: &str) -> Result<Self::Ok, Self::Error> { Ok(Some(DataType::Utf8)) }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Self::Error> { unsupported("bytes") }
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> { Ok(None) }
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Self::Ok, Self::Error> { value.serialize(self) }
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> { Ok(None) }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> { Ok(None) }
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, _variant: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(DataType::Utf8))
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        unsupported(name)
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> { unsupported("seq") }
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> { unsupported("tuple") }
+    fn serialize_tuple_struct(self, name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        unsupported(name)
+    }
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        unsupported(name)
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> { unsupported("map") }
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Self::Error> { unsupported(name) }
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        unsupported(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Default)]
+    struct Row {
+        name: String,
+        count: u32,
+        ratio: f64,
+        note: Option<String>,
+    }
+
+    fn row(name: &str, count: u32, note: Option<&str>) -> Row {
+        Row { name: name.to_string(), count, ratio: count as f64 / 2.0, note: note.map(str::to_string) }
+    }
+
+    /// テストごとに別の一時ファイルのパス（既存のファイルは削除しておく）
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("benchmark_common_{}_{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn json_rows(path: &Path) -> Vec<Value> {
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn csv_writes_header_and_rows() {
+        let path = temp_path("write.csv");
+        let mut writer = ResultWriter::create::<Row>(&path, OutputFormat::Csv, false).unwrap();
+        writer.serialize(row("a", 1, None)).unwrap();
+        writer.serialize(row("b", 3, Some("x"))).unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "name,count,ratio,note\na,1,0.5,\nb,3,1.5,x\n");
+    }
+
+    #[test]
+    fn ndjson_writes_one_object_per_line() {
+        let path = temp_path("write.ndjson");
+        let mut writer = ResultWriter::create::<Row>(&path, OutputFormat::Ndjson, false).unwrap();
+        writer.serialize(row("a", 1, None)).unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"name\":\"a\",\"count\":1,\"ratio\":0.5,\"note\":null}\n");
+    }
+
+    #[test]
+    fn json_writes_all_rows_on_flush() {
+        let path = temp_path("write.json");
+        let mut writer = ResultWriter::create::<Row>(&path, OutputFormat::Json, false).unwrap();
+        writer.serialize(row("a", 1, None)).unwrap();
+        assert!(!path.exists());
+
+        writer.flush().unwrap();
+        writer.serialize(row("b", 2, Some("x"))).unwrap();
+        writer.flush().unwrap();
+
+        let rows = json_rows(&path);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1]["note"], "x");
+    }
+
+    #[test]
+    fn parquet_uses_field_types() {
+        let path = temp_path("write.parquet");
+        let mut writer = ResultWriter::create::<Row>(&path, OutputFormat::Parquet, false).unwrap();
+        writer.serialize(row("a", 1, None)).unwrap();
+        writer.serialize(row("b", 2, Some("x"))).unwrap();
+        writer.flush().unwrap();
+
+        let (rows, types) = read_parquet(&path).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["count"], 1);
+        assert_eq!(rows[0]["note"], Value::Null);
+        assert_eq!(
+            types.0,
+            [
+                ("name".to_string(), Some(DataType::Utf8)),
+                ("count".to_string(), Some(DataType::UInt64)),
+                ("ratio".to_string(), Some(DataType::Float64)),
+                ("note".to_string(), Some(DataType::Utf8)),
+            ]
+        );
+    }
+
+    #[test]
+    fn unflushed_rows_are_written_on_drop() {
+        for format in [OutputFormat::Json, OutputFormat::Parquet] {
+            let path = temp_path(&format!("drop.{}", format.extension()));
+            let result: Result<(), Box<dyn std::error::Error>> = (|| {
+                let mut writer = ResultWriter::create::<Row>(&path, format, false)?;
+                writer.serialize(row("a", 1, None))?;
+                Err("測定エラー".into())
+            })();
+            assert!(result.is_err());
+
+            let rows = match format {
+                OutputFormat::Parquet => read_parquet(&path).unwrap().0,
+                _ => json_rows(&path),
+            };
+            assert_eq!(rows.len(), 1, "{:?}", format);
+        }
+    }
+
+    /// `rows` を書き込んでから、追記モードで `appended` を追記する
+    fn write_then_append(path: &Path, format: OutputFormat, rows: Vec<Row>, appended: Row) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = ResultWriter::create::<Row>(path, format, false)?;
+        for row in rows {
+            writer.serialize(row)?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        let mut writer = ResultWriter::create::<Row>(path, format, true)?;
+        writer.serialize(appended)?;
+        writer.flush()
+    }
+
+    #[derive(Serialize, Default)]
+    struct OtherRow {
+        name: String,
+        size: u64,
+    }
+
+    #[test]
+    fn csv_append_rejects_different_header() {
+        let path = temp_path("mismatch.csv");
+        fs::write(&path, "name,size
+a,1
+").unwrap();
+
+        let error = ResultWriter::create::<Row>(&path, OutputFormat::Csv, true).err().unwrap().to_string();
+        assert!(error.contains("列構成が一致しない"), "{}", error);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "name,size
+a,1
+");
+    }
+
+    #[test]
+    fn csv_append_keeps_header_once() {
+        let path = temp_path("append.csv");
+        write_then_append(&path, OutputFormat::Csv, vec![row("a", 1, None)], row("b", 2, None)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "name,count,ratio,note
+a,1,0.5,
+b,2,1.0,
+");
+    }
+
+    #[test]
+    fn ndjson_and_json_append_keep_existing_rows() {
+        let path = temp_path("append.ndjson");
+        write_then_append(&path, OutputFormat::Ndjson, vec![row("a", 1, None)], row("b", 2, None)).unwrap();
+        let names: Vec<Value> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["name"].clone())
+            .collect();
+        assert_eq!(names, ["a", "b"]);
+
+        let path = temp_path("append.json");
+        write_then_append(&path, OutputFormat::Json, vec![row("a", 1, None)], row("b", 2, None)).unwrap();
+        let names: Vec<Value> = json_rows(&path).iter().map(|row| row["name"].clone()).collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn json_append_rejects_different_columns() {
+        let path = temp_path("mismatch.json");
+        let mut writer = ResultWriter::create::<OtherRow>(&path, OutputFormat::Json, false).unwrap();
+        writer.serialize(OtherRow { name: "a".to_string(), size: 1 }).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        assert!(ResultWriter::create::<Row>(&path, OutputFormat::Json, true).is_err());
+    }
+
+    #[test]
+    fn parquet_append_round_trips_column_types() {
+        // 最初の書き込みでは note 列が全て欠損値のため、ファイルには型が残らない
+        let path = temp_path("append.parquet");
+        write_then_append(&path, OutputFormat::Parquet, vec![row("a", 1, None), row("b", 2, None)], row("c", 3, Some("x"))).unwrap();
+
+        let (rows, types) = read_parquet(&path).unwrap();
+        let names: Vec<Value> = rows.iter().map(|row| row["name"].clone()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert_eq!(rows[2]["note"], "x");
+        assert_eq!(rows[0]["count"], 1);
+        assert_eq!(rows[2]["ratio"], 1.5);
+        assert_eq!(
+            types.0,
+            [
+                ("name".to_string(), Some(DataType::Utf8)),
+                ("count".to_string(), Some(DataType::UInt64)),
+                ("ratio".to_string(), Some(DataType::Float64)),
+                ("note".to_string(), Some(DataType::Utf8)),
+            ]
+        );
+    }
+
+    #[test]
+    fn parquet_append_rejects_conflicting_column_types() {
+        #[derive(Serialize, Default)]
+        struct Row {
+            name: String,
+            count: f64,
+            ratio: f64,
+            note: Option<String>,
+        }
+
+        let path = temp_path("conflict.parquet");
+        write_then_append(&path, OutputFormat::Parquet, vec![row("a", 1, None)], row("b", 2, None)).unwrap();
+
+        let error = ResultWriter::create::<Row>(&path, OutputFormat::Parquet, true).err().unwrap().to_string();
+        assert!(error.contains("列 count の型が一致しません"), "{}", error);
+    }
+
+    #[test]
+    fn merge_fills_unknown_types_and_rejects_conflicts() {
+        let mut types = ColumnTypes(vec![("a".to_string(), None), ("b".to_string(), Some(DataType::UInt64))]);
+        types.merge(&ColumnTypes(vec![("a".to_string(), Some(DataType::Utf8)), ("b".to_string(), None)])).unwrap();
+        assert_eq!(types.0, [("a".to_string(), Some(DataType::Utf8)), ("b".to_string(), Some(DataType::UInt64))]);
+
+        let error = types.merge(&ColumnTypes(vec![("b".to_string(), Some(DataType::Float64))])).unwrap_err();
+        assert_eq!(error, "列 b の型が一致しません (既存: UInt64 / 今回: Float64)");
+    }
+
+    #[test]
+    fn retain_rows_removes_rows_in_every_format() {
+        for format in [OutputFormat::Csv, OutputFormat::Ndjson, OutputFormat::Json, OutputFormat::Parquet] {
+            let path = temp_path(&format!("retain.{}", format.extension()));
+            let mut writer = ResultWriter::create::<Row>(&path, format, false).unwrap();
+            for (name, count) in [("a", 1), ("b", 2), ("c", 3)] {
+                writer.serialize(row(name, count, None)).unwrap();
+            }
+            writer.flush().unwrap();
+            drop(writer);
+
+            let removed = retain_rows(&path, format, |column| column("count").as_deref() != Some("2")).unwrap();
+            assert_eq!(removed, 1, "{:?}", format);
+
+            // 残った行に追記できる
+            let mut writer = ResultWriter::create::<Row>(&path, format, true).unwrap();
+            writer.serialize(row("d", 4, Some("x"))).unwrap();
+            writer.flush().unwrap();
+            drop(writer);
+
+            let names: Vec<String> = match format {
+                OutputFormat::Csv => fs::read_to_string(&path).unwrap().lines().skip(1).map(|line| line[..1].to_string()).collect(),
+                OutputFormat::Ndjson => fs::read_to_string(&path)
+                    .unwrap()
+                    .lines()
+                    .map(|line| value_text(&serde_json::from_str::<Value>(line).unwrap()["name"]))
+                    .collect(),
+                OutputFormat::Json => json_rows(&path).iter().map(|row| value_text(&row["name"])).collect(),
+                OutputFormat::Parquet => read_parquet(&path).unwrap().0.iter().map(|row| value_text(&row["name"])).collect(),
+            };
+            assert_eq!(names, ["a", "c", "d"], "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn retain_rows_ignores_missing_file() {
+        let path = temp_path("missing.csv");
+        assert_eq!(retain_rows(&path, OutputFormat::Csv, |_| false).unwrap(), 0);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,76 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use tokio::task::JoinSet;
+
+use crate::interrupt;
+
+/// `--jobs` を省略した場合の並列数（利用可能な論理コア数）
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// 1, 2, 4, … と倍にしていき、最後は `max_jobs` で終わるワーカー数の列
+pub fn scaling_levels(max_jobs: usize) -> Vec<usize> {
+    let mut levels: Vec<usize> = std::iter::successors(Some(1), |&n| Some(n * 2))
+        .take_while(|&n| n < max_jobs)
+        .collect();
+    levels.push(max_jobs);
+    levels
+}
+
+/// `0..count` の各画像を、最大 `jobs` 個のワーカーで1枚ずつ取り合いながら処理する
+///
+/// 固定のチャンクに分割しないため、処理時間にばらつきがあっても空いたワーカーが次の画像を受け持つ。
+/// 中断フラグが立った後は新しい画像の処理を始めない。
+pub async fn for_each_image<F, Fut>(count: u32, jobs: usize, task: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: Fn(u32) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    let next = Arc::new(AtomicU32::new(0));
+    let task = Arc::new(task);
+    let mut join_set = JoinSet::new();
+
+    for _ in 0..jobs.clamp(1, count.max(1) as usize) {
+        let next = Arc::clone(&next);
+        let task = Arc::clone(&task);
+
+        join_set.spawn(async move {
+            while !interrupt::is_interrupted() {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= count {
+                    break;
+                }
+                task(i).await?;
+            }
+            Ok::<(), String>(())
+        });
+    }
+
+    // 全てのワーカーの完了を待つ
+    while let Some(result) = join_set.join_next().await {
+        match result {
+            Ok(Ok(())) => {},
+            Ok(Err(e)) => return Err(e.into()),
+            Err(e) => return Err(format!("並列実行エラー: {}", e).into()),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaling_levels_double_up_to_max_jobs() {
+        assert_eq!(scaling_levels(1), [1]);
+        assert_eq!(scaling_levels(2), [1, 2]);
+        assert_eq!(scaling_levels(6), [1, 2, 4, 6]);
+        assert_eq!(scaling_levels(8), [1, 2, 4, 8]);
+        assert_eq!(scaling_levels(9), [1, 2, 4, 8, 9]);
+    }
+}
@@ -0,0 +1,164 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::interrupt;
+use crate::manifest;
+
+/// ベンチマークで使う外部コマンド（各ベンチマークが一覧を `resolve` に渡す）
+///
+/// `name` は `--tool <名前>=<パス>` と環境変数 `BENCH_TOOL_<名前の大文字>`（例: `BENCH_TOOL_IMAGEMAGICK`）で使う名前。
+/// パスを指定しない場合は `candidates` を順に `PATH` から探す。
+pub struct Tool {
+    pub name: &'static str,
+    pub candidates: &'static [&'static str],
+    /// バージョンを表示させる引数
+    pub version_args: &'static [&'static str],
+    /// バージョンとして記録する行に含まれる文字列（空なら最初の行。複数あれば全ての行を ` / ` でつなぐ）
+    pub version_keywords: &'static [&'static str],
+}
+
+/// 使用する外部コマンドの絶対パスとバージョン（実行環境ファイルに記録する）
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ResolvedTool {
+    pub name: String,
+    /// 見つからなかった場合は空
+    pub path: String,
+    pub version: String,
+    /// `--tool` または環境変数でパスが指定された
+    #[serde(default)]
+    pub specified: bool,
+}
+
+static TOOLS: OnceLock<&'static [Tool]> = OnceLock::new();
+static RESOLVED: OnceLock<Vec<ResolvedTool>> = OnceLock::new();
+
+/// `tools` の全ての外部コマンドのパスとバージョンを決める
+///
+/// パスは `--tool` の指定 `overrides`、環境変数 `BENCH_TOOL_<名前>`、`PATH` の順に探す。
+/// 指定したパスが見つからない場合はエラーにする。`PATH` に見つからない場合は空のパスにしておき、
+/// 必要なコマンドかどうかは `require` で確認する。
+pub fn resolve(tools: &'static [Tool], overrides: &[(String, PathBuf)]) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some((name, _)) = overrides.iter().find(|(name, _)| !tools.iter().any(|tool| tool.name == name)) {
+        return Err(format!("--tool に未対応のコマンドが指定されました: {} (対応: {})", name, names(tools).join(", ")).into());
+    }
+
+    let mut resolved = Vec::new();
+    for tool in tools {
+        let specified = overrides.iter().rev().find(|(name, _)| name == tool.name).map(|(_, path)| path.clone())
+            .or_else(|| env::var_os(env_name(tool.name)).filter(|path| !path.is_empty()).map(PathBuf::from));
+
+        let path = match &specified {
+            Some(path) => Some(locate(path).ok_or_else(|| {
+                format!("{} に指定されたコマンドが見つかりません: {}（--tool または {}）", tool.name, path.display(), env_name(tool.name))
+            })?),
+            None => tool.candidates.iter().find_map(|candidate| locate(Path::new(candidate))),
+        };
+
+        let path = path.map(|path| path.display().to_string()).unwrap_or_default();
+        let version = if path.is_empty() {
+            "unknown".to_string()
+        } else {
+            let lines: Vec<String> = tool.version_keywords.iter()
+                .filter_map(|keyword| manifest::command_line(&path, tool.version_args, keyword))
+                .collect();
+            if lines.is_empty() { "unknown".to_string() } else { lines.join(" / ") }
+        };
+        resolved.push(ResolvedTool { name: tool.name.to_string(), path, version, specified: specified.is_some() });
+    }
+
+    let _ = TOOLS.set(tools);
+    let _ = RESOLVED.set(resolved);
+    Ok(())
+}
+
+/// 外部コマンド `name` が見つかっているか確認する
+///
+/// 画像を生成する前に呼ぶ。見つからない場合は指定方法を表示してエラーを返す。`user` は表示に使う、コマンドを使う処理の名前。
+pub fn require(name: &str, user: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if get(name).is_some_and(|tool| !tool.path.is_empty()) {
+        return Ok(());
+    }
+    println!("エラー: {} に必要な {} コマンドが見つかりません", user, name);
+    println!("PATHにない場合は `--tool {}=<パス>` または環境変数 {} で指定してください。", name, env_name(name));
+    if let Some(help) = install_help(name) {
+        println!("インストール: {}", help);
+    }
+    Err(format!("{} コマンドが見つかりません", name).into())
+}
+
+/// `resolve` で決めた全ての外部コマンド
+pub fn resolved() -> &'static [ResolvedTool] {
+    RESOLVED.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// `resolve` で決めた外部コマンドの情報
+pub fn get(name: &str) -> Option<&'static ResolvedTool> {
+    resolved().iter().find(|tool| tool.name == name)
+}
+
+/// `--tool` または環境変数でパスが指定されたか
+pub fn is_specified(name: &str) -> bool {
+    get(name).is_some_and(|tool| tool.specified)
+}
+
+/// 外部コマンドの実行に使うパス（見つかっていなければ候補の最初のコマンド名）
+pub fn path(name: &str) -> &str {
+    match get(name) {
+        Some(tool) if !tool.path.is_empty() => &tool.path,
+        _ => find(name).map(|tool| tool.candidates[0]).unwrap_or(name),
+    }
+}
+
+/// 外部コマンドを別のプロセスグループで起動する `Command` を作る
+pub fn command(name: &str) -> Command {
+    interrupt::command(path(name))
+}
+
+pub fn names(tools: &[Tool]) -> Vec<&'static str> {
+    tools.iter().map(|tool| tool.name).collect()
+}
+
+fn install_help(name: &str) -> Option<&'static str> {
+    match name {
+        "oxipng" => Some("cargo install oxipng"),
+        _ => None,
+    }
+}
+
+fn find(name: &str) -> Option<&'static Tool> {
+    TOOLS.get()?.iter().find(|tool| tool.name == name)
+}
+
+/// `imagemagick` なら `BENCH_TOOL_IMAGEMAGICK` のような、パスを指定する環境変数の名前
+fn env_name(name: &str) -> String {
+    let name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect();
+    format!("BENCH_TOOL_{}", name)
+}
+
+/// コマンドの絶対パス（パス区切りを含まない名前は `PATH` から探す）
+///
+/// 同じ実行ファイルでも呼び出す名前で動作が変わるコマンドがあるため、シンボリックリンクはたどらない。
+fn locate(program: &Path) -> Option<PathBuf> {
+    let path = if program.components().count() > 1 {
+        Some(program.to_path_buf()).filter(|path| is_executable(path))
+    } else {
+        env::split_paths(&env::var_os("PATH")?).map(|dir| dir.join(program)).find(|path| is_executable(path))
+    };
+    path.and_then(|path| std::path::absolute(path).ok())
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata().is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file() || path.with_extension("exe").is_file()
+    }
+}
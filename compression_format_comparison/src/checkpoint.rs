@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// 中断したベンチマークを `--resume` で再開するための進捗記録
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub run_id: String,
+    pub seed: u64,
+    pub image_count: u32,
+    /// 生成した画像の寸法・色の種類・ビット深度（例: `1024x1024 rgb 8bit`）
+    pub image_variant: String,
+    pub compression_runs: u32,
+    pub formats: Vec<String>,
+    pub original_size: u64,
+    pub completed: Vec<CompletedRun>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CompletedRun {
+    pub format: String,
+    pub run: u32,
+}
+
+/// 結果ファイルと同じ場所に置くチェックポイントのパス（`<結果ファイル名>.checkpoint.json`）
+pub fn checkpoint_path(results_file: &Path) -> PathBuf {
+    let mut path = results_file.as_os_str().to_owned();
+    path.push(".checkpoint.json");
+    PathBuf::from(path)
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("チェックポイントを読み込めません: {} ({})", path.display(), e))?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// 途中で書き込みが失敗しても壊れたファイルが残らないよう、一時ファイル経由で置き換える
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(self)? + "\n")?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// 再開時の引数がチェックポイント作成時と同じか確認する
+    pub fn check_compatible(&self, image_count: u32, image_variant: &str, compression_runs: u32, formats: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+        if self.image_count != image_count
+            || self.image_variant != image_variant
+            || self.compression_runs != compression_runs || self.formats != formats {
+            return Err(format!(
+                "チェックポイントと実行条件が一致しません (チェックポイント: {}枚（{}）、{}回、{} / 今回: {}枚（{}）、{}回、{})",
+                self.image_count,
+                self.image_variant,
+                self.compression_runs,
+                self.formats.join(","),
+                image_count,
+                image_variant,
+                compression_runs,
+                formats.join(",")
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    pub fn is_completed(&self, format: &str, run: u32) -> bool {
+        self.completed.iter().any(|c| c.format == format && c.run == run)
+    }
+
+    pub fn mark_completed(&mut self, format: &str, run: u32) {
+        self.completed.push(CompletedRun { format: format.to_string(), run });
+    }
+}
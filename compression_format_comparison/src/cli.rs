@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+
+use benchmark_common::output::OutputFormat;
+use benchmark_common::pool;
+use benchmark_common::tools::{self, Tool};
+
+use crate::variant::{self, ColorType, ImageVariant};
+
+/// このベンチマークで使う外部コマンド（`--tool` で指定できる名前）
+pub static TOOLS: &[Tool] = &[
+    Tool { name: "imagemagick", candidates: &["magick", "convert"], version_args: &["-version"], version_keywords: &["Version:"] },
+    Tool { name: "zip", candidates: &["zip"], version_args: &["-v"], version_keywords: &["This is Zip"] },
+    Tool { name: "tar", candidates: &["tar"], version_args: &["--version"], version_keywords: &[""] },
+    Tool { name: "gzip", candidates: &["gzip"], version_args: &["--version"], version_keywords: &[""] },
+    Tool { name: "zstd", candidates: &["zstd"], version_args: &["--version"], version_keywords: &[""] },
+    Tool { name: "xz", candidates: &["xz"], version_args: &["--version"], version_keywords: &[""] },
+    // 7-Zip は配布元によってコマンド名が異なる（p7zip の 7z / 7za、本家の 7zz）
+    Tool { name: "7z", candidates: &["7z", "7zz", "7za"], version_args: &[], version_keywords: &["7-Zip"] },
+];
+
+/// 位置引数以外のコマンドラインオプション
+pub struct Options {
+    pub output_format: OutputFormat,
+    pub output: Option<PathBuf>,
+    pub append: bool,
+    pub jobs: usize,
+    pub resume: bool,
+    pub seed: Option<u64>,
+    /// `--size` / `--color-type` / `--bit-depth` で指定した生成する画像
+    pub variant: ImageVariant,
+    /// `--tool <名前>=<パス>` で指定した外部コマンドのパス
+    pub tools: Vec<(String, PathBuf)>,
+}
+
+/// `--name value` / `--name=value` 形式のオプションを取り出し、残りの位置引数と合わせて返す
+pub fn split_options(args: &[String]) -> Result<(Options, Vec<String>), Box<dyn std::error::Error>> {
+    let mut options = Options {
+        output_format: OutputFormat::Csv,
+        output: None,
+        append: false,
+        jobs: pool::default_jobs(),
+        resume: false,
+        seed: None,
+        variant: variant::DEFAULT,
+        tools: Vec::new(),
+    };
+    let mut positionals = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            positionals.push(arg.clone());
+            continue;
+        }
+
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| iter.next().cloned())
+                .ok_or_else(|| format!("{} には値を指定してください", name))
+        };
+
+        match name {
+            "--output-format" => options.output_format = OutputFormat::parse(&value()?)?,
+            "--output" => options.output = Some(PathBuf::from(value()?)),
+            "--append" => options.append = true,
+            "--jobs" => match value()?.parse() {
+                Ok(jobs) if jobs > 0 => options.jobs = jobs,
+                _ => return Err("--jobs は1以上の整数で指定してください".into()),
+            },
+            "--resume" => options.resume = true,
+            "--seed" => {
+                options.seed = Some(value()?.parse().map_err(|_| "--seed は0以上の整数で指定してください")?)
+            },
+            "--size" => {
+                let size = value()?;
+                let (width, height) = variant::parse_size(&size).ok_or_else(|| format!("--size は 幅x高さ で指定してください: {}", size))?;
+                options.variant.width = width;
+                options.variant.height = height;
+            },
+            "--color-type" => options.variant.color_type = ColorType::parse(&value()?)?,
+            "--bit-depth" => options.variant.bit_depth = variant::parse_bit_depth(&value()?)?,
+            "--tool" => {
+                let value = value()?;
+                let (tool, path) = value.split_once('=').filter(|(tool, path)| !tool.is_empty() && !path.is_empty())
+                    .ok_or_else(|| format!("--tool は <名前>=<パス> で指定してください: {}", value))?;
+                options.tools.push((tool.to_string(), PathBuf::from(path)));
+            },
+            _ => return Err(format!("不明なオプションです: {}", name).into()),
+        }
+    }
+
+    Ok((options, positionals))
+}
+
+pub fn print_options_help() {
+    println!("  -h, --help                このヘルプメッセージを表示");
+    println!("  --output-format <形式>    結果の出力形式: csv, json, ndjson, parquet (デフォルト: csv)");
+    println!("  --output <パス>           結果の出力先パス (デフォルト: 結果ファイル名.<形式の拡張子>)");
+    println!("  --append                  既存の結果ファイルを上書きせず追記（列構成が異なる場合はエラー）");
+    println!("  --jobs <数>               ベンチマーク画像の生成を並列に実行するワーカー数 (デフォルト: 論理コア数)");
+    println!("  --resume                  チェックポイントから中断したベンチマークを再開");
+    println!("  --seed <数値>             画像生成の乱数シード (デフォルト: ランダム、チェックポイントに記録)");
+    println!("  --size <幅x高さ>          生成する画像の寸法 (デフォルト: 1024x1024)");
+    println!("  --color-type <種類>       生成する画像の色の種類: gray, rgb, rgba (デフォルト: rgb)");
+    println!("  --bit-depth <数>          生成する画像のビット深度: 8, 16 (デフォルト: 8)");
+    println!("  --tool <名前>=<パス>      外部コマンドのパスを指定 (対応: {}、環境変数 BENCH_TOOL_<名前> でも可)", tools::names(TOOLS).join(", "));
+}
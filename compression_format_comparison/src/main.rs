@@ -1,17 +1,32 @@
+mod checkpoint;
+mod cli;
+mod variant;
+
 use std::fs;
 use std::process::Command;
-use csv::Writer;
 use serde::Serialize;
 use rand::{thread_rng, Rng};
-use tokio::task::JoinSet;
 use std::sync::Arc;
 use std::env;
 use std::time::Instant;
+use std::path::Path;
+use checkpoint::Checkpoint;
+use imagemagick::Requirement;
+use cli::Options;
+use manifest::RunManifest;
+use benchmark_common::{imagemagick, interrupt, manifest, pool, tools};
+use benchmark_common::output::{self, ResultWriter};
+use variant::ImageVariant;
 
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 struct CompressionStats {
+    run_id: String,
     run_number: u32,
     format: String,
+    width: u32,
+    height: u32,
+    channels: u8,
+    bit_depth: u8,
     original_size: u64,
     compressed_size: u64,
     compression_ratio: f64,
@@ -21,33 +36,36 @@ struct CompressionStats {
 
 fn print_help() {
     println!("データ圧縮フォーマット比較ベンチマーク");
-    println!("");
+    println!();
     println!("使用方法:");
     println!("  {} [画像枚数] [圧縮回数]", env::args().next().unwrap_or_else(|| "program".to_string()));
-    println!("");
+    println!();
     println!("引数:");
     println!("  画像枚数    圧縮対象の画像枚数 (デフォルト: 100)");
     println!("  圧縮回数    各フォーマットでの圧縮実行回数 (デフォルト: 100)");
-    println!("");
+    println!();
     println!("オプション:");
-    println!("  -h, --help  このヘルプメッセージを表示");
-    println!("");
+    cli::print_options_help();
+    println!();
     println!("例:");
     println!("  {}           # デフォルト: 100枚、100回圧縮", env::args().next().unwrap_or_else(|| "program".to_string()));
     println!("  {} 50        # 50枚、100回圧縮", env::args().next().unwrap_or_else(|| "program".to_string()));
     println!("  {} 200 50    # 200枚、50回圧縮", env::args().next().unwrap_or_else(|| "program".to_string()));
-    println!("");
+    println!("  {} 100 100 --output-format parquet  # Parquet で出力", env::args().next().unwrap_or_else(|| "program".to_string()));
+    println!();
     println!("対応フォーマット: ZIP, TAR.GZ, ZSTD, XZ, 7Z");
 }
 
-fn parse_args() -> Result<(u32, u32), Box<dyn std::error::Error>> {
+fn parse_args() -> Result<(u32, u32, Options), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
     
-    if args.len() > 1 && (args[1] == "--help" || args[1] == "-h") {
+    if args.iter().skip(1).any(|arg| arg == "--help" || arg == "-h") {
         print_help();
         std::process::exit(0);
     }
     
+    let (options, args) = cli::split_options(&args)?;
+    
     let image_count = if args.len() > 1 {
         args[1].parse::<u32>()
             .map_err(|_| "画像枚数は正の整数で指定してください")?
@@ -70,43 +88,123 @@ fn parse_args() -> Result<(u32, u32), Box<dyn std::error::Error>> {
         return Err("圧縮回数は1以上で指定してください".into());
     }
     
-    Ok((image_count, compression_runs))
+    Ok((image_count, compression_runs, options))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let (image_count, compression_runs) = parse_args()?;
+    let (image_count, compression_runs, options) = parse_args()?;
+    interrupt::install()?;
     
     println!("データ圧縮フォーマット比較ベンチマーク開始");
     println!("画像枚数: {}枚、各フォーマット{}回圧縮実行", image_count, compression_runs);
+    println!("画像: {}", options.variant.label());
+    println!("並列数: {}", options.jobs);
+    
+    // 外部コマンドのパスとバージョンを決める（--tool、環境変数 BENCH_TOOL_<名前>、PATH の順）
+    tools::resolve(cli::TOOLS, &options.tools)?;
     
     // 必要なコマンドの確認
     check_required_commands()?;
     
-    let mut csv_writer = Writer::from_path("compression_format_comparison_results.csv")?;
+    let output_path = options.output.clone()
+        .unwrap_or_else(|| output::default_path("compression_format_comparison_results", options.output_format));
+    let checkpoint_path = checkpoint::checkpoint_path(&output_path);
     
     // 圧縮フォーマット
     let formats = ["zip", "tar.gz", "zstd", "xz", "7z"];
     
-    // ベンチマーク用ディレクトリ作成
+    // ベンチマーク用ディレクトリ
     let benchmark_dir = "benchmark_images";
-    fs::create_dir_all(benchmark_dir)?;
     
-    // 画像ファイル生成（8並列）
-    println!("ベンチマーク用画像{}枚を生成中...", image_count);
-    generate_random_png_images_parallel(benchmark_dir, image_count).await?;
-    let original_size = calculate_directory_size(benchmark_dir)?;
-    println!("画像生成完了: 総サイズ {:.2} MB", original_size as f64 / 1024.0 / 1024.0);
+    // 再開時はチェックポイントの実行IDとシードを引き継ぎ、結果ファイルに追記する
+    let resumed = if options.resume {
+        let checkpoint = Checkpoint::load(&checkpoint_path)?;
+        checkpoint.check_compatible(image_count, &options.variant.label(), compression_runs, &formats)?;
+        println!("チェックポイントから再開します: {}/{} 完了済み", checkpoint.completed.len(), formats.len() as u32 * compression_runs);
+        
+        // 結果を書き込んでからチェックポイントに記録するまでの間に中断した行は、もう一度測定するため取り除く
+        let removed = output::retain_rows(&output_path, options.output_format, |column| {
+            let run = column("run_number").and_then(|run| run.parse().ok()).unwrap_or_default();
+            let format = column("format").unwrap_or_default().to_lowercase();
+            column("run_id").as_deref() != Some(checkpoint.run_id.as_str()) || checkpoint.is_completed(&format, run)
+        })?;
+        if removed > 0 {
+            println!("チェックポイントに記録されていない{}行を結果ファイルから取り除きました", removed);
+        }
+        Some(checkpoint)
+    } else {
+        None
+    };
+    
+    let mut result_writer = ResultWriter::create::<CompressionStats>(&output_path, options.output_format, options.append || resumed.is_some())?;
+    
+    // 実行環境を結果ファイルと同じ場所に記録（再開時は記録済み）
+    let manifest_path = manifest::manifest_path(&output_path);
+    let run_id = match &resumed {
+        Some(checkpoint) => checkpoint.run_id.clone(),
+        None => {
+            let manifest = RunManifest::collect(&output_path, Path::new(benchmark_dir), tools::resolved());
+            manifest.write_next_to(&output_path, options.append)?;
+            manifest.run_id
+        },
+    };
+    println!("実行ID: {}", run_id);
+    
+    // 画像ファイル生成（--jobs 並列）。再開時は同じシードで同一の画像群を用意する
+    let seed = resumed.as_ref().map(|c| c.seed).or(options.seed).unwrap_or_else(|| thread_rng().r#gen());
+    let original_size = prepare_benchmark_images(benchmark_dir, &options.variant, image_count, seed, options.jobs, resumed.as_ref().map(|c| c.original_size)).await?;
+    println!("画像準備完了: 総サイズ {:.2} MB (シード: {})", original_size as f64 / 1024.0 / 1024.0, seed);
+    
+    let mut checkpoint = resumed.unwrap_or_else(|| Checkpoint {
+        run_id: run_id.clone(),
+        seed,
+        image_count,
+        image_variant: options.variant.label(),
+        compression_runs,
+        formats: formats.iter().map(|f| f.to_string()).collect(),
+        original_size,
+        completed: Vec::new(),
+    });
+    
+    // 画像生成中に中断された場合は、途中までの画像を削除して終了する（チェックポイントは更新しない）
+    if interrupt::is_interrupted() {
+        fs::remove_dir_all(benchmark_dir)?;
+        println!("画像生成中に中断されました。");
+        std::process::exit(interrupt::EXIT_INTERRUPTED);
+    }
+    
+    checkpoint.save(&checkpoint_path)?;
+    println!("チェックポイント: {} (中断した場合は --resume で再開できます)", checkpoint_path.display());
     
     // 各フォーマットで圧縮テスト
     for format in &formats {
+        if interrupt::is_interrupted() {
+            break;
+        }
+        
         println!("\\n{}フォーマット圧縮テスト開始...", format.to_uppercase());
         
+        // このフォーマットの圧縮に使うコマンド（実行環境ファイルにも記録する）
+        if let Some(tool) = compressor(format).and_then(tools::get) {
+            println!("  {}: {} ({})", tool.name, tool.path, tool.version);
+        }
+        
         for run in 1..=compression_runs {
+            if interrupt::is_interrupted() {
+                break;
+            }
+            if checkpoint.is_completed(format, run) {
+                continue;
+            }
+            
             if run % 10 == 0 || run == 1 {
                 println!("  {}: {}/{} 実行中", format.to_uppercase(), run, compression_runs);
             }
             
+            // 前回の中断で残った圧縮ファイルがあれば削除しておく
+            cleanup_compressed_file(format, run)?;
+            
             let start_time = Instant::now();
             let compressed_size = compress_directory(benchmark_dir, format, run).await?;
             let compression_time = start_time.elapsed();
@@ -115,8 +213,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let compression_speed = (original_size as f64 / 1024.0 / 1024.0) / compression_time.as_secs_f64();
             
             let stats = CompressionStats {
+                run_id: run_id.clone(),
                 run_number: run,
                 format: format.to_uppercase().to_string(),
+                width: options.variant.width,
+                height: options.variant.height,
+                channels: options.variant.color_type.channels(),
+                bit_depth: options.variant.bit_depth,
                 original_size,
                 compressed_size,
                 compression_ratio,
@@ -124,7 +227,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 compression_speed_mbps: compression_speed,
             };
             
-            csv_writer.serialize(&stats)?;
+            result_writer.serialize(&stats)?;
+            result_writer.flush()?;
+            checkpoint.mark_completed(format, run);
+            checkpoint.save(&checkpoint_path)?;
             
             // 圧縮ファイルを削除（ディスク容量節約）
             cleanup_compressed_file(format, run)?;
@@ -133,64 +239,121 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("  {}フォーマット完了", format.to_uppercase());
     }
     
-    csv_writer.flush()?;
+    result_writer.flush()?;
     
     // クリーンアップ
     fs::remove_dir_all(benchmark_dir)?;
     cleanup_remaining_files(&formats)?;
     
+    // 中断時はチェックポイントを残し、--resume で続きから再開できるようにする
+    if interrupt::is_interrupted() {
+        println!("中断されました。完了した分の結果は{}に保存されました。", output_path.display());
+        println!("続きから再開するには同じ引数に --resume を付けて実行してください。");
+        std::process::exit(interrupt::EXIT_INTERRUPTED);
+    }
+    
+    fs::remove_file(&checkpoint_path)?;
+    
     println!("\\n全ての圧縮テストが完了しました。");
-    println!("結果はcompression_format_comparison_results.csvに保存されました。");
+    println!("結果は{}に保存されました。", output_path.display());
+    println!("実行環境は{}に保存されました。", manifest_path.display());
     
     Ok(())
 }
 
-async fn generate_random_png_images_parallel(output_dir: &str, count: u32) -> Result<(), Box<dyn std::error::Error>> {
-    let mut join_set = JoinSet::new();
+/// ベンチマーク用画像を用意し、総サイズを返す
+///
+/// 再開時（`expected_size` あり）は、残っている画像の総サイズが元と同じならそのまま使い、
+/// 異なる場合は同じシードで作り直して元と一致することを確認する。
+async fn prepare_benchmark_images(
+    benchmark_dir: &str,
+    variant: &ImageVariant,
+    image_count: u32,
+    seed: u64,
+    jobs: usize,
+    expected_size: Option<u64>,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    if let Some(expected) = expected_size
+        && fs::metadata(benchmark_dir).is_ok()
+        && calculate_directory_size(benchmark_dir)? == expected
+    {
+        println!("既存のベンチマーク用画像を再利用します");
+        return Ok(expected);
+    }
+    
+    // 前回の中断で残った画像は作り直す
+    if fs::metadata(benchmark_dir).is_ok() {
+        fs::remove_dir_all(benchmark_dir)?;
+    }
+    fs::create_dir_all(benchmark_dir)?;
+    
+    println!("ベンチマーク用画像{}枚を生成中...", image_count);
+    generate_random_png_images_parallel(benchmark_dir, variant, image_count, seed, jobs).await?;
+    let total_size = calculate_directory_size(benchmark_dir)?;
+    
+    // 中断された場合は画像が揃っていないため照合しない（呼び出し側で終了処理を行う）
+    if let Some(expected) = expected_size
+        && !interrupt::is_interrupted()
+        && total_size != expected
+    {
+        return Err(format!(
+            "再生成した画像の総サイズ({})がチェックポイント({})と一致しません。ImageMagickのバージョンが変わった可能性があります",
+            total_size, expected
+        ).into());
+    }
+    
+    Ok(total_size)
+}
+
+async fn generate_random_png_images_parallel(output_dir: &str, variant: &ImageVariant, count: u32, seed: u64, jobs: usize) -> Result<(), Box<dyn std::error::Error>> {
     let output_dir = Arc::new(output_dir.to_string());
+    let variant = *variant;
     
-    let chunk_size = count / 8;
-    for thread_id in 0..8 {
-        let start = thread_id * chunk_size;
-        let end = if thread_id == 7 { count } else { (thread_id + 1) * chunk_size };
-        let output_dir_clone = Arc::clone(&output_dir);
+    pool::for_each_image(count, jobs, move |i| {
+        let output_dir = Arc::clone(&output_dir);
         
-        join_set.spawn(async move {
-            let mut rng = thread_rng();
-            for i in start..end {
-                let r: u8 = rng.r#gen();
-                let g: u8 = rng.r#gen();
-                let b: u8 = rng.r#gen();
-                
-                let output_path = format!("{}/image_{:03}.png", output_dir_clone.as_str(), i);
+        async move {
+            let output_path = format!("{}/image_{:03}.png", output_dir.as_str(), i);
+            
+            // 画像ごとにシードを決めることで、並列数や生成順に関係なく同じ画像を再現できる
+            let status = variant.generate_command(seed.wrapping_add(i as u64), &output_path)
+                .status()
+                .await
+                .map_err(|e| format!("画像生成コマンド実行エラー: {}", e))?;
                 
-                let status = Command::new("convert")
-                    .args(&[
-                        "-size", "1024x1024",
-                        &format!("xc:rgb({},{},{})", r, g, b),
-                        "+noise", "Random",
-                        &output_path
-                    ])
-                    .status()
-                    .map_err(|e| format!("画像生成コマンド実行エラー: {}", e))?;
-                    
-                if !status.success() {
-                    return Err(format!("画像生成に失敗しました: {}", output_path));
-                }
+            if !status.success() {
+                return Err(format!("画像生成に失敗しました: {}", output_path));
             }
-            Ok::<(), String>(())
-        });
-    }
-    
-    while let Some(result) = join_set.join_next().await {
-        match result {
-            Ok(Ok(())) => {},
-            Ok(Err(e)) => return Err(e.into()),
-            Err(e) => return Err(format!("並列実行エラー: {}", e).into()),
+            Ok(())
         }
+    })
+    .await
+}
+
+/// フォーマットの圧縮に使うコマンド（`tools` の名前）
+fn compressor(format: &str) -> Option<&'static str> {
+    match format {
+        "zip" => Some("zip"),
+        "tar.gz" => Some("gzip"),
+        "zstd" => Some("zstd"),
+        "xz" => Some("xz"),
+        "7z" => Some("7z"),
+        _ => None,
     }
-    
-    Ok(())
+}
+
+/// `dir_path` をtarでまとめ、`compressor` で圧縮した `archive` を作る
+///
+/// 通常はtarの `-czf` / `-cJf` のように `create_flags` で圧縮させ、tarが `PATH` から圧縮コマンドを探す。
+/// `--tool` または環境変数で圧縮コマンドが指定されている場合のみ、`--use-compress-program` でそのパスを渡す。
+async fn tar_with_compressor(compressor: &str, create_flags: &str, archive: &str, dir_path: &str) -> std::io::Result<std::process::ExitStatus> {
+    let mut command = tools::command("tar");
+    if tools::is_specified(compressor) {
+        command.args(["-cf", archive, "--use-compress-program", tools::path(compressor)]);
+    } else {
+        command.args([create_flags, archive]);
+    }
+    command.args(["-C", ".", dir_path]).status().await
 }
 
 async fn compress_directory(dir_path: &str, format: &str, run_number: u32) -> Result<u64, Box<dyn std::error::Error>> {
@@ -198,45 +361,41 @@ async fn compress_directory(dir_path: &str, format: &str, run_number: u32) -> Re
     
     let status = match format {
         "zip" => {
-            Command::new("zip")
-                .args(&["-r", "-q", &format!("{}.zip", output_file), dir_path])
-                .status()?
+            tools::command("zip")
+                .args(["-r", "-q", &format!("{}.zip", output_file), dir_path])
+                .status().await?
         },
         "tar.gz" => {
-            Command::new("tar")
-                .args(&["-czf", &format!("{}.tar.gz", output_file), "-C", ".", dir_path])
-                .status()?
+            tar_with_compressor("gzip", "-czf", &format!("{}.tar.gz", output_file), dir_path).await?
         },
         "zstd" => {
             // まずtarで一時ファイル作成
             let temp_tar = format!("{}.tar", output_file);
-            let tar_status = Command::new("tar")
-                .args(&["-cf", &temp_tar, "-C", ".", dir_path])
-                .status()?;
+            let tar_status = tools::command("tar")
+                .args(["-cf", &temp_tar, "-C", ".", dir_path])
+                .status().await?;
             
             if !tar_status.success() {
                 return Err("tarファイル作成に失敗".into());
             }
             
             // zstdで圧縮
-            let status = Command::new("zstd")
-                .args(&[&temp_tar, "-o", &format!("{}.tar.zst", output_file)])
-                .status()?;
+            let status = tools::command("zstd")
+                .args([&temp_tar, "-o", &format!("{}.tar.zst", output_file)])
+                .status().await?;
                 
             // 一時tarファイル削除
             let _ = fs::remove_file(&temp_tar);
             status
         },
         "xz" => {
-            Command::new("tar")
-                .args(&["-cJf", &format!("{}.tar.xz", output_file), "-C", ".", dir_path])
-                .status()?
+            tar_with_compressor("xz", "-cJf", &format!("{}.tar.xz", output_file), dir_path).await?
         },
         "7z" => {
-            Command::new("7z")
-                .args(&["a", "-t7z", &format!("{}.7z", output_file), dir_path])
+            tools::command("7z")
+                .args(["a", "-t7z", &format!("{}.7z", output_file), dir_path])
                 .stdout(std::process::Stdio::null())
-                .status()?
+                .status().await?
         },
         _ => return Err(format!("未対応のフォーマット: {}", format).into()),
     };
@@ -304,10 +463,13 @@ fn cleanup_remaining_files(formats: &[&str]) -> Result<(), Box<dyn std::error::E
 }
 
 fn check_required_commands() -> Result<(), Box<dyn std::error::Error>> {
+    // 画像の生成に使うImageMagickは、PNGを書き込めるかまで確認する
+    imagemagick::preflight(&[Requirement { coder: "PNG", read: false, write: true }])?;
+    
     let commands = [
-        ("convert", "ImageMagick"),
         ("zip", "zip"),
         ("tar", "tar"),
+        ("gzip", "gzip"),
         ("zstd", "zstandard"),
         ("xz", "xz-utils"),
         ("7z", "p7zip-full"),
@@ -316,7 +478,7 @@ fn check_required_commands() -> Result<(), Box<dyn std::error::Error>> {
     let mut missing_commands = Vec::new();
     
     for (cmd, package) in &commands {
-        match Command::new(cmd).arg("--help").output() {
+        match Command::new(tools::path(cmd)).arg("--help").output() {
             Ok(_) => {},
             Err(_) => {
                 missing_commands.push((*cmd, *package));
@@ -329,10 +491,10 @@ fn check_required_commands() -> Result<(), Box<dyn std::error::Error>> {
         for (cmd, package) in &missing_commands {
             println!("  {} (パッケージ: {})", cmd, package);
         }
-        println!("\\nUbuntu/Debianでのインストール:");
+        println!("\nUbuntu/Debianでのインストール:");
         println!("  sudo apt-get update");
         println!("  sudo apt-get install imagemagick zip tar zstd xz-utils p7zip-full");
-        println!("\\nmacOSでのインストール:");
+        println!("\nmacOSでのインストール:");
         println!("  brew install imagemagick zstd xz p7zip");
         
         return Err("必要なコマンドが不足しています".into());
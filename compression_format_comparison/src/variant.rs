@@ -0,0 +1,111 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::process::Command;
+use benchmark_common::imagemagick;
+
+/// 生成する画像の色の種類
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorType {
+    Gray,
+    Rgb,
+    Rgba,
+}
+
+impl ColorType {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "gray" | "grey" => Ok(ColorType::Gray),
+            "rgb" => Ok(ColorType::Rgb),
+            "rgba" => Ok(ColorType::Rgba),
+            _ => Err(format!("未対応の色の種類です: {} (対応: gray, rgb, rgba)", value)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorType::Gray => "gray",
+            ColorType::Rgb => "rgb",
+            ColorType::Rgba => "rgba",
+        }
+    }
+
+    pub fn channels(self) -> u8 {
+        match self {
+            ColorType::Gray => 1,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+}
+
+/// 生成する画像の寸法・色の種類・ビット深度の組
+#[derive(Clone, Copy, PartialEq)]
+pub struct ImageVariant {
+    pub width: u32,
+    pub height: u32,
+    pub color_type: ColorType,
+    pub bit_depth: u8,
+}
+
+/// これまでの固定の生成条件（1024x1024、8bit RGB）
+pub const DEFAULT: ImageVariant = ImageVariant { width: 1024, height: 1024, color_type: ColorType::Rgb, bit_depth: 8 };
+
+impl ImageVariant {
+    /// `1024x1024 rgb 8bit` のような表示用の名前
+    pub fn label(&self) -> String {
+        format!("{}x{} {} {}bit", self.width, self.height, self.color_type.name(), self.bit_depth)
+    }
+
+    /// 画像ごとのシード `seed` からノイズ画像を生成し、PNGとして `output` に保存するコマンド
+    ///
+    /// 同じシードであれば、並列数や生成順に関係なくバイト単位で同じ画像になる。
+    pub fn generate_command(&self, seed: u64, output: &str) -> Command {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let r: u8 = rng.r#gen();
+        let g: u8 = rng.r#gen();
+        let b: u8 = rng.r#gen();
+        let noise_seed: u32 = rng.r#gen();
+        let size = format!("{}x{}", self.width, self.height);
+
+        let mut command = imagemagick::command();
+        command.args([
+            "-seed", &noise_seed.to_string(),
+            "-size", &size,
+            &format!("xc:rgb({},{},{})", r, g, b),
+            "+noise", "Random",
+        ]);
+        match self.color_type {
+            ColorType::Gray => {
+                command.args(["-colorspace", "Gray", "-type", "Grayscale"]);
+            },
+            ColorType::Rgb => {},
+            // 別のノイズ画像を透明度として重ね、画素ごとに異なる透明度にする
+            ColorType::Rgba => {
+                command.args(["(", "-size", &size, "xc:gray50", "+noise", "Random", "-colorspace", "Gray", ")"]);
+                command.args(["-alpha", "off", "-compose", "CopyOpacity", "-composite", "-type", "TrueColorAlpha"]);
+            },
+        }
+        if self.bit_depth != 8 {
+            command.args(["-depth", &self.bit_depth.to_string()]);
+        }
+        // 作成日時のチャンクを含めず、同じシードならバイト単位で同じ画像にする
+        command.args(["-define", "png:exclude-chunk=date,time"]).arg(output);
+        command
+    }
+}
+
+/// `1024x1024` のような1つの寸法を解釈する
+pub fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.trim().split_once(['x', 'X'])?;
+    let width: u32 = width.parse().ok().filter(|&width| width > 0)?;
+    let height: u32 = height.parse().ok().filter(|&height| height > 0)?;
+    Some((width, height))
+}
+
+pub fn parse_bit_depth(value: &str) -> Result<u8, String> {
+    match value.trim() {
+        "8" => Ok(8),
+        "16" => Ok(16),
+        _ => Err(format!("ビット深度は8または16で指定してください: {}", value)),
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use benchmark_common::output::OutputFormat;
+
+/// 位置引数以外のコマンドラインオプション
+pub struct Options {
+    pub output_format: OutputFormat,
+    pub output: Option<PathBuf>,
+    pub append: bool,
+}
+
+/// `--name value` / `--name=value` 形式のオプションを取り出し、残りの位置引数と合わせて返す
+pub fn split_options(args: &[String]) -> Result<(Options, Vec<String>), Box<dyn std::error::Error>> {
+    let mut options = Options {
+        output_format: OutputFormat::Csv,
+        output: None,
+        append: false,
+    };
+    let mut positionals = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            positionals.push(arg.clone());
+            continue;
+        }
+
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| iter.next().cloned())
+                .ok_or_else(|| format!("{} には値を指定してください", name))
+        };
+
+        match name {
+            "--output-format" => options.output_format = OutputFormat::parse(&value()?)?,
+            "--output" => options.output = Some(PathBuf::from(value()?)),
+            "--append" => options.append = true,
+            _ => return Err(format!("不明なオプションです: {}", name).into()),
+        }
+    }
+
+    Ok((options, positionals))
+}
+
+pub fn print_options_help() {
+    println!("  -h, --help                このヘルプメッセージを表示");
+    println!("  --output-format <形式>    結果の出力形式: csv, json, ndjson, parquet (デフォルト: csv)");
+    println!("  --output <パス>           結果の出力先パス (デフォルト: 結果ファイル名.<形式の拡張子>)");
+    println!("  --append                  既存の結果ファイルを上書きせず追記（列構成が異なる場合はエラー）");
+}
@@ -1,13 +1,19 @@
+mod cli;
+
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 use std::env;
-use csv::Writer;
 use serde::Serialize;
+use cli::Options;
+use manifest::RunManifest;
+use benchmark_common::{interrupt, manifest};
+use benchmark_common::output::{self, ResultWriter};
 
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 struct BenchmarkResult {
+    run_id: String,
     run_number: u32,
     target_path: String,
     file_count: u32,
@@ -17,32 +23,35 @@ struct BenchmarkResult {
 
 fn print_help() {
     println!("ファイル書き込みベンチマーク（テストプログラム）");
-    println!("");
+    println!();
     println!("使用方法:");
     println!("  {} <書き込み先パス> [ファイル数] [実行回数]", env::args().next().unwrap_or_else(|| "program".to_string()));
-    println!("");
+    println!();
     println!("引数:");
     println!("  書き込み先パス  ファイルを作成するディレクトリパス (必須)");
     println!("  ファイル数      各実行で作成するファイル数 (デフォルト: 1000)");
     println!("  実行回数        ベンチマークの実行回数 (デフォルト: 3)");
-    println!("");
+    println!();
     println!("オプション:");
-    println!("  -h, --help  このヘルプメッセージを表示");
-    println!("");
+    cli::print_options_help();
+    println!();
     println!("例:");
     println!("  {} /tmp/benchmark", env::args().next().unwrap_or_else(|| "program".to_string()));
     println!("  {} F:/benchmark 500", env::args().next().unwrap_or_else(|| "program".to_string()));
+    println!("  {} /tmp/benchmark --output-format parquet --output results.parquet", env::args().next().unwrap_or_else(|| "program".to_string()));
 }
 
-fn parse_args() -> Result<(PathBuf, u32, u32), Box<dyn std::error::Error>> {
+fn parse_args() -> Result<(PathBuf, u32, u32, Options), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
 
     // ヘルプの表示
-    if args.len() > 1 && (args[1] == "--help" || args[1] == "-h") {
+    if args.iter().skip(1).any(|arg| arg == "--help" || arg == "-h") {
         print_help();
         std::process::exit(0);
     }
 
+    let (options, args) = cli::split_options(&args)?;
+
     // 書き込み先パスは必須
     if args.len() < 2 {
         print_help();
@@ -73,7 +82,7 @@ fn parse_args() -> Result<(PathBuf, u32, u32), Box<dyn std::error::Error>> {
         return Err("実行回数は1以上で指定してください".into());
     }
 
-    Ok((target_path, file_count, runs))
+    Ok((target_path, file_count, runs, options))
 }
 
 fn create_benchmark_files(base_path: &Path, file_count: u32) -> Result<(), Box<dyn std::error::Error>> {
@@ -81,12 +90,16 @@ fn create_benchmark_files(base_path: &Path, file_count: u32) -> Result<(), Box<d
     let data = [0u8; 1];
 
     for i in 0..file_count {
+        if interrupt::is_interrupted() {
+            break;
+        }
+
         let file_path = base_path.join(format!("file_{:08}.dat", i));
         let mut file = File::create(&file_path)?;
         file.write_all(&data)?;
 
         // 進捗表示（100ファイルごと）
-        if (i + 1) % 100 == 0 {
+        if (i + 1).is_multiple_of(100) {
             println!("  作成済み: {}/{} ファイル", i + 1, file_count);
         }
     }
@@ -102,24 +115,38 @@ fn cleanup_benchmark_files(base_path: &Path) -> Result<(), Box<dyn std::error::E
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let (target_path, file_count, runs) = parse_args()?;
+    let (target_path, file_count, runs, options) = parse_args()?;
+    interrupt::install()?;
 
     println!("ファイル書き込みベンチマーク開始（テストモード）");
     println!("書き込み先: {}", target_path.display());
     println!("ファイル数: {}ファイル", file_count);
     println!("実行回数: {}回", runs);
-    println!("");
+    println!();
 
     // 親ディレクトリが存在するか確認
-    if let Some(parent) = target_path.parent() {
-        if !parent.exists() {
-            return Err(format!("親ディレクトリが存在しません: {}", parent.display()).into());
-        }
+    if let Some(parent) = target_path.parent()
+        && !parent.exists()
+    {
+        return Err(format!("親ディレクトリが存在しません: {}", parent.display()).into());
     }
 
-    let mut csv_writer = Writer::from_path("file_write_benchmark_test_results.csv")?;
+    let output_path = options.output.clone()
+        .unwrap_or_else(|| output::default_path("file_write_benchmark_test_results", options.output_format));
+    let mut result_writer = ResultWriter::create::<BenchmarkResult>(&output_path, options.output_format, options.append)?;
+
+    // 実行環境（ファイルシステム種別など）を結果ファイルと同じ場所に記録
+    let manifest = RunManifest::collect(&output_path, &target_path, &[]);
+    let manifest_path = manifest.write_next_to(&output_path, options.append)?;
+    println!("実行ID: {}", manifest.run_id);
+    println!("ファイルシステム: {}", manifest.filesystem_type);
+    println!();
 
     for run in 1..=runs {
+        if interrupt::is_interrupted() {
+            break;
+        }
+
         println!("実行回数: {}/{}", run, runs);
 
         // ベンチマーク用ディレクトリを作成
@@ -131,6 +158,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         create_benchmark_files(&benchmark_dir, file_count)?;
         let elapsed = start.elapsed();
 
+        // 中断された回は途中までのファイル数しか作成していないため記録せず、作成済みのファイルを削除する
+        if interrupt::is_interrupted() {
+            println!("  中断されました。クリーンアップ中...");
+            cleanup_benchmark_files(&benchmark_dir)?;
+            break;
+        }
+
         let elapsed_ms = elapsed.as_millis();
         let files_per_second = (file_count as f64) / elapsed.as_secs_f64();
 
@@ -140,23 +174,31 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         // 結果を記録
         let result = BenchmarkResult {
+            run_id: manifest.run_id.clone(),
             run_number: run,
             target_path: target_path.display().to_string(),
             file_count,
             total_time_ms: elapsed_ms,
             files_per_second,
         };
-        csv_writer.serialize(result)?;
+        result_writer.serialize(result)?;
 
         // クリーンアップ
         println!("  クリーンアップ中...");
         cleanup_benchmark_files(&benchmark_dir)?;
-        println!("");
+        println!();
+    }
+
+    result_writer.flush()?;
+
+    if interrupt::is_interrupted() {
+        println!("中断されました。完了した回の結果は{}に保存されました", output_path.display());
+        std::process::exit(interrupt::EXIT_INTERRUPTED);
     }
 
-    csv_writer.flush()?;
     println!("テストベンチマーク完了！");
-    println!("結果はfile_write_benchmark_test_results.csvに保存されました");
+    println!("結果は{}に保存されました", output_path.display());
+    println!("実行環境は{}に保存されました", manifest_path.display());
 
     Ok(())
 }
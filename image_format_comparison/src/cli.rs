@@ -0,0 +1,190 @@
+use std::path::PathBuf;
+
+use benchmark_common::output::OutputFormat;
+use benchmark_common::pool;
+use benchmark_common::tools::{self, Tool};
+
+use crate::derivative;
+use crate::formats::{self, ImageFormat};
+use crate::icc;
+use crate::metadata::{self, MetadataBlock};
+use crate::variant::{self, ImageVariant};
+
+/// このベンチマークで使う外部コマンド（`--tool` で指定できる名前）
+pub static TOOLS: &[Tool] = &[
+    Tool { name: "imagemagick", candidates: &["magick", "convert"], version_args: &["-version"], version_keywords: &["Version:"] },
+    Tool { name: "oxipng", candidates: &["oxipng"], version_args: &["--version"], version_keywords: &["oxipng"] },
+];
+
+/// 位置引数以外のコマンドラインオプション
+pub struct Options {
+    pub output_format: OutputFormat,
+    pub output: Option<PathBuf>,
+    pub append: bool,
+    pub jobs: usize,
+    pub seed: Option<u64>,
+    pub details: bool,
+    /// `--sizes` / `--color-types` / `--bit-depths` / `--alpha-patterns` / `--color-spaces` の全ての組み合わせ
+    pub variants: Vec<ImageVariant>,
+    pub scaling: bool,
+    /// 派生画像（レスポンシブ画像）の作成を測定するモード
+    pub derivatives: bool,
+    /// 派生画像の幅（大きい順）
+    pub ladder: Vec<u32>,
+    /// 派生画像の縮小に使うリサンプリングフィルタ
+    pub filters: Vec<&'static str>,
+    pub formats: Vec<&'static ImageFormat>,
+    pub lossless: bool,
+    /// `--lossless` で `--formats` を省略した（ImageMagickの対応を確認してから JXL-LOSSLESS を加える）
+    pub lossless_default: bool,
+    /// 元画像に埋め込むメタデータ（空なら埋め込まない）
+    pub metadata: Vec<MetadataBlock>,
+    pub strip: bool,
+    /// `--color-spaces` を指定した場合に、カラーマネジメント後の色差（ΔE2000）を測る
+    pub delta_e: bool,
+    /// `--tool <名前>=<パス>` で指定した外部コマンドのパス
+    pub tools: Vec<(String, PathBuf)>,
+}
+
+/// `--name value` / `--name=value` 形式のオプションを取り出し、残りの位置引数と合わせて返す
+pub fn split_options(args: &[String]) -> Result<(Options, Vec<String>), Box<dyn std::error::Error>> {
+    let mut options = Options {
+        output_format: OutputFormat::Csv,
+        output: None,
+        append: false,
+        jobs: pool::default_jobs(),
+        seed: None,
+        details: false,
+        variants: vec![variant::DEFAULT],
+        scaling: false,
+        derivatives: false,
+        ladder: derivative::DEFAULT_LADDER.to_vec(),
+        filters: derivative::FILTERS.to_vec(),
+        formats: formats::default_formats(),
+        lossless: false,
+        lossless_default: false,
+        metadata: Vec::new(),
+        strip: false,
+        delta_e: false,
+        tools: Vec::new(),
+    };
+    let mut positionals = Vec::new();
+    let mut sizes = vec![(variant::DEFAULT.width, variant::DEFAULT.height)];
+    let mut color_types = vec![variant::DEFAULT.color_type];
+    let mut bit_depths = vec![variant::DEFAULT.bit_depth];
+    let mut alpha_patterns = vec![variant::DEFAULT.alpha_pattern];
+    let mut color_spaces = vec![variant::DEFAULT.color_space];
+    let mut formats_given = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            positionals.push(arg.clone());
+            continue;
+        }
+
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| iter.next().cloned())
+                .ok_or_else(|| format!("{} には値を指定してください", name))
+        };
+
+        match name {
+            "--output-format" => options.output_format = OutputFormat::parse(&value()?)?,
+            "--output" => options.output = Some(PathBuf::from(value()?)),
+            "--append" => options.append = true,
+            "--jobs" => match value()?.parse() {
+                Ok(jobs) if jobs > 0 => options.jobs = jobs,
+                _ => return Err("--jobs は1以上の整数で指定してください".into()),
+            },
+            "--seed" => {
+                options.seed = Some(value()?.parse().map_err(|_| "--seed は0以上の整数で指定してください")?)
+            },
+            "--details" => options.details = true,
+            "--sizes" => sizes = variant::parse_sizes(&value()?)?,
+            "--color-types" => color_types = variant::parse_color_types(&value()?)?,
+            "--bit-depths" => bit_depths = variant::parse_bit_depths(&value()?)?,
+            "--alpha-patterns" => alpha_patterns = variant::parse_alpha_patterns(&value()?)?,
+            "--color-spaces" => {
+                color_spaces = icc::parse_color_spaces(&value()?)?;
+                options.delta_e = true;
+            },
+            "--scaling" => options.scaling = true,
+            "--derivatives" => options.derivatives = true,
+            "--ladder" => options.ladder = derivative::parse_ladder(&value()?)?,
+            "--filters" => options.filters = derivative::parse_filters(&value()?)?,
+            "--formats" => {
+                options.formats = formats::parse_list(&value()?)?;
+                formats_given = true;
+            },
+            "--lossless" => options.lossless = true,
+            "--metadata" => options.metadata = metadata::parse_list(&value()?)?,
+            "--strip" => options.strip = true,
+            "--tool" => {
+                let value = value()?;
+                let (tool, path) = value.split_once('=').filter(|(tool, path)| !tool.is_empty() && !path.is_empty())
+                    .ok_or_else(|| format!("--tool は <名前>=<パス> で指定してください: {}", value))?;
+                options.tools.push((tool.to_string(), PathBuf::from(path)));
+            },
+            _ => return Err(format!("不明なオプションです: {}", name).into()),
+        }
+    }
+
+    if options.scaling && options.derivatives {
+        return Err("--scaling と --derivatives は同時に指定できません".into());
+    }
+
+    if options.lossless && !formats_given {
+        options.formats = formats::lossless_formats();
+        options.lossless_default = true;
+    }
+
+    options.variants = variant::matrix(&sizes, &color_types, &bit_depths, &alpha_patterns, &color_spaces);
+
+    Ok((options, positionals))
+}
+
+pub fn print_options_help() {
+    println!("  -h, --help                このヘルプメッセージを表示");
+    println!("  --output-format <形式>    結果の出力形式: csv, json, ndjson, parquet (デフォルト: csv)");
+    println!("  --output <パス>           結果の出力先パス (デフォルト: 結果ファイル名.<形式の拡張子>)");
+    println!("  --append                  既存の結果ファイルを上書きせず追記（列構成が異なる場合はエラー）");
+    println!("  --jobs <数>               画像の生成・変換を並列に実行するワーカー数 (デフォルト: 論理コア数)");
+    println!("  --seed <数値>             画像生成の乱数シード (デフォルト: ランダム)");
+    println!("  --details                 画像ごとの結果を <結果ファイル名>_details.<拡張子> に出力");
+    println!("  --sizes <一覧>            生成する画像の寸法をカンマ区切りで指定 (例: 128x128,4000x3000)");
+    println!("                            (thumbnails: 128x128,256x256,512x512 / photo: 4000x3000、デフォルト: 1024x1024)");
+    println!("  --color-types <一覧>      生成する画像の色の種類: gray, rgb, rgba (デフォルト: rgb)");
+    println!("  --bit-depths <一覧>       生成する画像のビット深度: 8, 16 (デフォルト: 8)");
+    println!("  --alpha-patterns <一覧>   RGBA画像の透明度: noise, gradient, cutout, opaque, all (デフォルト: noise)");
+    println!("  --color-spaces <一覧>     RGB画像の色空間: srgb, display-p3, adobe-rgb, all (デフォルト: srgb)");
+    println!("                            (指定すると変換後の色差 ΔE2000 も測定)");
+    println!("                            (寸法・色の種類・ビット深度・透明度・色空間は全ての組み合わせを比較)");
+    println!("  --scaling                 変換のワーカー数を1, 2, 4, …, --jobs と変えてスループットを測定");
+    println!("  --derivatives             元画像ごとに幅の異なる派生画像を作り、合計サイズとCPU時間を測定");
+    println!(
+        "  --ladder <一覧>           派生画像の幅 (デフォルト: {})",
+        derivative::DEFAULT_LADDER.iter().map(|width| width.to_string()).collect::<Vec<_>>().join(",")
+    );
+    println!("  --filters <一覧>          派生画像の縮小フィルタ: {}, all (デフォルト: all)", derivative::FILTERS.join(", "));
+    println!("  --formats <一覧>          比較するフォーマットをカンマ区切りで指定");
+    println!("                            (対応: {})", formats::names().join(", "));
+    println!(
+        "                            (デフォルト: {})",
+        formats::default_formats().iter().map(|format| format.name).collect::<Vec<_>>().join(", ")
+    );
+    println!("  --lossless                可逆圧縮のフォーマットを比較するモード（PNG比を表示）");
+    println!(
+        "                            (--formats 省略時: {}、ImageMagickがJPEG XLを読み書きできれば {} も)",
+        formats::lossless_formats().iter().map(|format| format.name).collect::<Vec<_>>().join(", "),
+        formats::OPTIONAL_LOSSLESS_FORMATS.join(", ")
+    );
+    println!("  --metadata <一覧>         元画像に埋め込むメタデータ: exif, icc, xmp, all（変換後に残ったかとサイズを記録）");
+    println!("  --strip                   メタデータを除いて（-strip）変換した場合とのサイズを比較");
+    println!("  --tool <名前>=<パス>      外部コマンドのパスを指定 (対応: {}、環境変数 BENCH_TOOL_<名前> でも可)", tools::names(TOOLS).join(", "));
+}
@@ -0,0 +1,250 @@
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+use benchmark_common::imagemagick;
+use benchmark_common::pool;
+
+use crate::formats::{self, ImageFormat};
+use crate::icc::{self, RgbColorSpace};
+use crate::metadata::{self, MetadataBlock};
+
+/// 変換後の画像と元画像の色差（CIEDE2000）の比較結果
+pub struct ColorDifference {
+    /// 全画像・全画素のΔE2000の平均
+    pub mean_delta_e: f64,
+    /// 全画像・全画素のΔE2000の最大
+    pub max_delta_e: f64,
+    /// 全画像がICCプロファイルを保持していたか
+    pub profile_kept: bool,
+}
+
+/// 画像サイズが元画像と異なり画素を対応付けられない場合の色差（Lab の明度の全範囲）
+const MISMATCH_DELTA_E: f64 = 100.0;
+
+/// 画像をImageMagickでデコードし、`args` を適用したあとのRGBの値（0〜1）の列として返す
+async fn decode_rgb(path: &str, args: &[&str]) -> Result<Vec<[f64; 3]>, String> {
+    let output = imagemagick::command()
+        .arg(path)
+        .args(args)
+        .args(["-depth", "16", "-endian", "MSB", "rgb:-"])
+        .output()
+        .await
+        .map_err(|e| format!("デコードコマンド実行エラー: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("画像のデコードに失敗しました: {}", path));
+    }
+    Ok(output.stdout.chunks_exact(6).map(|pixel| {
+        let channel = |index: usize| u16::from_be_bytes([pixel[index], pixel[index + 1]]) as f64 / 65535.0;
+        [channel(0), channel(2), channel(4)]
+    }).collect())
+}
+
+/// 全画像について、変換後の画像をカラーマネジメントしたうえで元画像と色差を比較する
+///
+/// 元画像の画素値は `color_space` の値としてそのまま使う。変換後の画像は埋め込まれたICCプロファイルから
+/// `color_space` に変換し、プロファイルがなければsRGBとみなして変換する（ブラウザなどと同じ扱い）。
+/// そのため、広色域の画像でプロファイルが失われると、その分の色のずれが色差として現れる。
+pub async fn compare_colors(
+    output_dir: &str,
+    format: &'static ImageFormat,
+    color_space: &'static RgbColorSpace,
+    count: u32,
+    jobs: usize,
+) -> Result<ColorDifference, Box<dyn std::error::Error>> {
+    let srgb_path = format!("{}/srgb.icc", output_dir);
+    let target_path = format!("{}/{}.icc", output_dir, color_space.name);
+    fs::write(&srgb_path, icc::SRGB.profile())?;
+    fs::write(&target_path, color_space.profile())?;
+
+    let output_dir = Arc::new(output_dir.to_string());
+    let profiles = Arc::new((srgb_path.clone(), target_path.clone()));
+    // ΔEの合計、画素数、最大、プロファイルを保持していたか
+    let totals = Arc::new(Mutex::new((0.0, 0u64, 0.0f64, true)));
+    let totals_clone = Arc::clone(&totals);
+
+    let result = pool::for_each_image(count, jobs, move |i| {
+        let output_dir = Arc::clone(&output_dir);
+        let profiles = Arc::clone(&profiles);
+        let totals = Arc::clone(&totals_clone);
+
+        async move {
+            let (srgb_path, target_path) = &*profiles;
+            let source_path = formats::source().file_path(&output_dir, i);
+            let output_path = format.file_path(&output_dir, i);
+
+            let profile_kept = metadata::block_size(&output_path, MetadataBlock::Icc).await?.is_some();
+            // プロファイルのない画像は、先にsRGBを割り当ててから変換する
+            let args: Vec<&str> = if profile_kept {
+                vec!["-profile", target_path.as_str()]
+            } else {
+                vec!["-profile", srgb_path.as_str(), "-profile", target_path.as_str()]
+            };
+            let source = decode_rgb(&source_path, &[]).await?;
+            let decoded = decode_rgb(&output_path, &args).await?;
+
+            let (sum, max) = if source.len() != decoded.len() {
+                (MISMATCH_DELTA_E * source.len() as f64, MISMATCH_DELTA_E)
+            } else {
+                let to_lab = lab_converter(color_space);
+                source.iter().zip(&decoded).fold((0.0, 0.0f64), |(sum, max), (a, b)| {
+                    let delta_e = delta_e2000(to_lab(*a), to_lab(*b));
+                    (sum + delta_e, max.max(delta_e))
+                })
+            };
+
+            let mut totals = totals.lock().unwrap();
+            totals.0 += sum;
+            totals.1 += source.len() as u64;
+            totals.2 = totals.2.max(max);
+            totals.3 &= profile_kept;
+            Ok(())
+        }
+    })
+    .await;
+
+    let _ = fs::remove_file(&srgb_path);
+    let _ = fs::remove_file(&target_path);
+    result?;
+
+    let (sum, pixels, max, profile_kept) = *totals.lock().unwrap();
+    Ok(ColorDifference {
+        mean_delta_e: if pixels > 0 { sum / pixels as f64 } else { 0.0 },
+        max_delta_e: max,
+        profile_kept,
+    })
+}
+
+/// `color_space` のRGBの値（0〜1）を、D50を白色点とするCIELABに変換する関数
+fn lab_converter(color_space: &RgbColorSpace) -> impl Fn([f64; 3]) -> [f64; 3] {
+    let matrix = color_space.rgb_to_xyz_d50();
+    let transfer = color_space.transfer;
+    move |rgb| {
+        let xyz = icc::multiply_vector(matrix, rgb.map(|value| transfer.to_linear(value)));
+        let f = |t: f64| if t > 216.0 / 24389.0 { t.cbrt() } else { (24389.0 / 27.0 * t + 16.0) / 116.0 };
+        let [fx, fy, fz] = [f(xyz[0] / icc::D50[0]), f(xyz[1] / icc::D50[1]), f(xyz[2] / icc::D50[2])];
+        [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+    }
+}
+
+/// CIEDE2000の色差（重み係数 kL = kC = kH = 1）
+fn delta_e2000(lab1: [f64; 3], lab2: [f64; 3]) -> f64 {
+    let [l1, a1, b1] = lab1;
+    let [l2, a2, b2] = lab2;
+
+    // 彩度の平均に応じて a* を補正する
+    let c_mean7 = ((a1.hypot(b1) + a2.hypot(b2)) / 2.0).powi(7);
+    let g = 0.5 * (1.0 - (c_mean7 / (c_mean7 + 25f64.powi(7))).sqrt());
+    let (a1, a2) = (a1 * (1.0 + g), a2 * (1.0 + g));
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let hue = |a: f64, b: f64| if a == 0.0 && b == 0.0 { 0.0 } else { b.atan2(a).to_degrees().rem_euclid(360.0) };
+    let (h1, h2) = (hue(a1, b1), hue(a2, b2));
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 <= h1 {
+        h2 - h1 + 360.0
+    } else {
+        h2 - h1 - 360.0
+    };
+    let delta_big_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let l_mean = (l1 + l2) / 2.0;
+    let c_mean = (c1 + c2) / 2.0;
+    let h_mean = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_mean - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_mean).to_radians().cos()
+        + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_mean - 63.0).to_radians().cos();
+    let delta_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (c_mean.powi(7) / (c_mean.powi(7) + 25f64.powi(7))).sqrt();
+    let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_mean;
+    let s_h = 1.0 + 0.015 * c_mean * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let (l, c, h) = (delta_l / s_l, delta_c / s_c, delta_big_h / s_h);
+    (l * l + c * c + h * h + r_t * c * h).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sharma, Wu, Dalal (2005) "The CIEDE2000 Color-Difference Formula" の検証用データ（L*a*b* の組と ΔE00）
+    const SHARMA_PAIRS: &[([f64; 3], [f64; 3], f64)] = &[
+        ([50.0000, 2.6772, -79.7751], [50.0000, 0.0000, -82.7485], 2.0425),
+        ([50.0000, 3.1571, -77.2803], [50.0000, 0.0000, -82.7485], 2.8615),
+        ([50.0000, 2.8361, -74.0200], [50.0000, 0.0000, -82.7485], 3.4412),
+        ([50.0000, -1.3802, -84.2814], [50.0000, 0.0000, -82.7485], 1.0000),
+        ([50.0000, -1.1848, -84.8006], [50.0000, 0.0000, -82.7485], 1.0000),
+        ([50.0000, -0.9009, -85.5211], [50.0000, 0.0000, -82.7485], 1.0000),
+        ([50.0000, 0.0000, 0.0000], [50.0000, -1.0000, 2.0000], 2.3669),
+        ([50.0000, -1.0000, 2.0000], [50.0000, 0.0000, 0.0000], 2.3669),
+        ([50.0000, 2.4900, -0.0010], [50.0000, -2.4900, 0.0009], 7.1792),
+        ([50.0000, 2.4900, -0.0010], [50.0000, -2.4900, 0.0010], 7.1792),
+        ([50.0000, 2.4900, -0.0010], [50.0000, -2.4900, 0.0011], 7.2195),
+        ([50.0000, 2.4900, -0.0010], [50.0000, -2.4900, 0.0012], 7.2195),
+        ([50.0000, -0.0010, 2.4900], [50.0000, 0.0009, -2.4900], 4.8045),
+        ([50.0000, -0.0010, 2.4900], [50.0000, 0.0010, -2.4900], 4.8045),
+        ([50.0000, -0.0010, 2.4900], [50.0000, 0.0011, -2.4900], 4.7461),
+        ([50.0000, 2.5000, 0.0000], [50.0000, 0.0000, -2.5000], 4.3065),
+        ([50.0000, 2.5000, 0.0000], [73.0000, 25.0000, -18.0000], 27.1492),
+        ([50.0000, 2.5000, 0.0000], [61.0000, -5.0000, 29.0000], 22.8977),
+        ([50.0000, 2.5000, 0.0000], [56.0000, -27.0000, -3.0000], 31.9030),
+        ([50.0000, 2.5000, 0.0000], [58.0000, 24.0000, 15.0000], 19.4535),
+        ([50.0000, 2.5000, 0.0000], [50.0000, 3.1736, 0.5854], 1.0000),
+        ([50.0000, 2.5000, 0.0000], [50.0000, 3.2972, 0.0000], 1.0000),
+        ([50.0000, 2.5000, 0.0000], [50.0000, 1.8634, 0.5757], 1.0000),
+        ([50.0000, 2.5000, 0.0000], [50.0000, 3.2592, 0.3350], 1.0000),
+        ([60.2574, -34.0099, 36.2677], [60.4626, -34.1751, 39.4387], 1.2644),
+        ([63.0109, -31.0961, -5.8663], [62.8187, -29.7946, -4.0864], 1.2630),
+        ([61.2901, 3.7196, -5.3901], [61.4292, 2.2480, -4.9620], 1.8731),
+        ([35.0831, -44.1164, 3.7933], [35.0232, -40.0716, 1.5901], 1.8645),
+        ([22.7233, 20.0904, -46.6940], [23.0331, 14.9730, -42.5619], 2.0373),
+        ([36.4612, 47.8580, 18.3852], [36.2715, 50.5065, 21.2231], 1.4146),
+        ([90.8027, -2.0831, 1.4410], [91.1528, -1.6435, 0.0447], 1.4441),
+        ([90.9257, -0.5406, -0.9208], [88.6381, -0.8985, -0.7239], 1.5381),
+        ([6.7747, -0.2908, -2.4247], [5.8714, -0.0985, -2.2286], 0.6377),
+        ([2.0776, 0.0795, -1.1350], [0.9033, -0.0636, -0.5514], 0.9082),
+    ];
+
+    #[test]
+    fn delta_e2000_matches_sharma_reference_pairs() {
+        for (index, &(lab1, lab2, expected)) in SHARMA_PAIRS.iter().enumerate() {
+            let actual = delta_e2000(lab1, lab2);
+            assert!((actual - expected).abs() < 1e-4, "組 {}: {} (期待値 {})", index + 1, actual, expected);
+            // 色差は対称
+            assert!((delta_e2000(lab2, lab1) - actual).abs() < 1e-9, "組 {} が対称ではない", index + 1);
+        }
+    }
+
+    #[test]
+    fn delta_e2000_is_zero_for_identical_colors() {
+        assert_eq!(delta_e2000([50.0, 10.0, -20.0], [50.0, 10.0, -20.0]), 0.0);
+        assert_eq!(delta_e2000([0.0, 0.0, 0.0], [0.0, 0.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn lab_converter_maps_white_and_black() {
+        for color_space in icc::COLOR_SPACES {
+            let to_lab = lab_converter(color_space);
+            let [l, a, b] = to_lab([1.0, 1.0, 1.0]);
+            assert!((l - 100.0).abs() < 1e-6 && a.abs() < 1e-6 && b.abs() < 1e-6, "{}: 白が {:?}", color_space.name, [l, a, b]);
+            assert_eq!(to_lab([0.0, 0.0, 0.0]), [0.0, 0.0, 0.0], "{}: 黒", color_space.name);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use benchmark_common::pool;
+
+use crate::formats::{self, ImageFormat};
+
+/// `--filters` で指定できるリサンプリングフィルタ（ImageMagickの `-filter` の名前）
+pub static FILTERS: &[&str] = &["triangle", "catrom", "mitchell", "lanczos"];
+
+/// `--ladder` を省略した場合に作る派生画像の幅
+pub const DEFAULT_LADDER: &[u32] = &[2048, 1024, 512, 256];
+
+/// `lanczos,mitchell` のようなフィルタの一覧を解釈する（`all` で全て）
+pub fn parse_filters(value: &str) -> Result<Vec<&'static str>, String> {
+    if value.trim().eq_ignore_ascii_case("all") {
+        return Ok(FILTERS.to_vec());
+    }
+
+    let mut filters = Vec::new();
+    for name in value.split(',').map(|name| name.trim().to_ascii_lowercase()).filter(|name| !name.is_empty()) {
+        let filter = FILTERS.iter().copied().find(|filter| *filter == name)
+            .ok_or_else(|| format!("未対応のフィルタです: {} (対応: {}, all)", name, FILTERS.join(", ")))?;
+        if !filters.contains(&filter) {
+            filters.push(filter);
+        }
+    }
+
+    if filters.is_empty() {
+        return Err("--filters には1つ以上のフィルタを指定してください".to_string());
+    }
+    Ok(filters)
+}
+
+/// `2048,1024,512,256` のような派生画像の幅の一覧を解釈する（大きい順に並べる）
+pub fn parse_ladder(value: &str) -> Result<Vec<u32>, String> {
+    let mut widths = Vec::new();
+    for item in value.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let width: u32 = item.parse().ok().filter(|&width| width > 0)
+            .ok_or_else(|| format!("派生画像の幅は1以上の整数で指定してください: {}", item))?;
+        if !widths.contains(&width) {
+            widths.push(width);
+        }
+    }
+
+    if widths.is_empty() {
+        return Err("--ladder には1つ以上の幅を指定してください".to_string());
+    }
+    widths.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(widths)
+}
+
+/// これまでに終了を待った子プロセス全体が使ったCPU時間（ユーザー＋システム、秒）
+///
+/// 変換は外部コマンドで行うため、このプロセス自身ではなく子プロセスの使用量（`RUSAGE_CHILDREN`）を見る。
+/// 計測の前後の差を取って使う。
+#[cfg(unix)]
+pub fn children_cpu_seconds() -> f64 {
+    // SAFETY: rusage はゼロで初期化でき、getrusage は渡したポインタの先にのみ書き込む
+    let usage = unsafe {
+        let mut usage: libc::rusage = std::mem::zeroed();
+        libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage);
+        usage
+    };
+    let seconds = |time: libc::timeval| time.tv_sec as f64 + time.tv_usec as f64 / 1_000_000.0;
+    seconds(usage.ru_utime) + seconds(usage.ru_stime)
+}
+
+/// `getrusage` のない環境では子プロセスのCPU時間を測れないため NaN を返す
+#[cfg(not(unix))]
+pub fn children_cpu_seconds() -> f64 {
+    f64::NAN
+}
+
+/// 全画像について幅 `widths` の派生画像をこのフォーマットで作り、全派生画像の合計サイズを返す
+///
+/// 派生画像は `<出力先>/derivatives/w<幅>/` に作り、サイズを測ったあとディレクトリごと削除する。
+pub async fn ladder_total_size(
+    output_dir: &str,
+    format: &'static ImageFormat,
+    filter: &'static str,
+    widths: &[u32],
+    count: u32,
+    jobs: usize,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let derivatives_dir = format!("{}/derivatives", output_dir);
+    for width in widths {
+        fs::create_dir_all(format!("{}/w{}", derivatives_dir, width))?;
+    }
+
+    let source_dir = Arc::new(output_dir.to_string());
+    let target_dir = Arc::new(derivatives_dir.clone());
+    let widths = Arc::new(widths.to_vec());
+    let total_size = Arc::new(AtomicU64::new(0));
+    let total_size_clone = Arc::clone(&total_size);
+
+    let result = pool::for_each_image(count, jobs, move |i| {
+        let source_dir = Arc::clone(&source_dir);
+        let target_dir = Arc::clone(&target_dir);
+        let widths = Arc::clone(&widths);
+        let total_size = Arc::clone(&total_size_clone);
+
+        async move {
+            let input_path = formats::source().file_path(&source_dir, i);
+            for &width in widths.iter() {
+                let output_path = format.file_path(&format!("{}/w{}", target_dir, width), i);
+                for mut command in format.resize_commands(&input_path, &output_path, filter, width) {
+                    let status = command
+                        .status()
+                        .await
+                        .map_err(|e| format!("派生画像の作成コマンド実行エラー: {}", e))?;
+                    if !status.success() {
+                        return Err(format!("派生画像の作成に失敗しました: {}", output_path));
+                    }
+                }
+                let size = fs::metadata(&output_path).map_err(|e| format!("{}: {}", output_path, e))?.len();
+                total_size.fetch_add(size, Ordering::Relaxed);
+            }
+            Ok(())
+        }
+    })
+    .await;
+
+    fs::remove_dir_all(&derivatives_dir)?;
+    result?;
+    Ok(total_size.load(Ordering::Relaxed))
+}
@@ -0,0 +1,225 @@
+use tokio::process::Command;
+use benchmark_common::imagemagick::{self, Requirement};
+use benchmark_common::tools;
+
+/// 比較対象の画像フォーマット
+///
+/// `name` は結果の `format` 列と `--formats` で使う名前。同じ拡張子で設定違いのものを
+/// 同じディレクトリに置けるよう、名前が拡張子と異なる場合はファイル名に名前を含める。
+pub struct ImageFormat {
+    pub name: &'static str,
+    pub extension: &'static str,
+    /// ImageMagickの `-list format` での名前
+    pub coder: &'static str,
+    pub encoder: Encoder,
+    /// 元画像と画素単位で一致するはずの最大のビット深度（非可逆のフォーマットは0）
+    ///
+    /// 可逆圧縮でも8bitまでしか保存できないフォーマットは、16bitの画像では可逆にならない。
+    pub lossless_bit_depth: u8,
+}
+
+/// 生成したPNGから各フォーマットを作る方法（元画像のPNGでは使わない）
+pub enum Encoder {
+    /// `convert <元画像> <オプション> <出力>`
+    Convert(&'static [&'static str]),
+    /// `oxipng <オプション> --out <出力> <元画像>`（PNGのまま再圧縮して最適化する）
+    Oxipng(&'static [&'static str]),
+}
+
+/// 生成元の画像フォーマット（変換せず、生成したファイルをそのまま計測する）
+const SOURCE_NAME: &str = "png";
+
+pub static FORMATS: &[ImageFormat] = &[
+    ImageFormat { name: "png", extension: "png", coder: "PNG", encoder: Encoder::Convert(&[]), lossless_bit_depth: 16 },
+    ImageFormat { name: "png-optimized", extension: "png", coder: "PNG", encoder: Encoder::Oxipng(&["-o", "4", "--strip", "safe"]), lossless_bit_depth: 16 },
+    // Adam7 でインターレースしたPNG（低速な回線でも粗い全体像から順に表示できる）
+    ImageFormat { name: "png-interlaced", extension: "png", coder: "PNG", encoder: Encoder::Convert(&["-interlace", "PNG"]), lossless_bit_depth: 16 },
+    ImageFormat { name: "jpg", extension: "jpg", coder: "JPEG", encoder: Encoder::Convert(&["-quality", "100"]), lossless_bit_depth: 0 },
+    // 色差の間引き（クロマサブサンプリング）を明示したJPEG。jpg ではImageMagickが品質から選ぶ
+    ImageFormat { name: "jpg-444", extension: "jpg", coder: "JPEG", encoder: Encoder::Convert(&["-quality", "100", "-sampling-factor", "4:4:4"]), lossless_bit_depth: 0 },
+    ImageFormat { name: "jpg-422", extension: "jpg", coder: "JPEG", encoder: Encoder::Convert(&["-quality", "100", "-sampling-factor", "4:2:2"]), lossless_bit_depth: 0 },
+    ImageFormat { name: "jpg-420", extension: "jpg", coder: "JPEG", encoder: Encoder::Convert(&["-quality", "100", "-sampling-factor", "4:2:0"]), lossless_bit_depth: 0 },
+    // プログレッシブJPEG（jpg と同じ品質で、スキャンを分けて符号化する）
+    ImageFormat {
+        name: "jpg-progressive",
+        extension: "jpg",
+        coder: "JPEG",
+        encoder: Encoder::Convert(&["-quality", "100", "-interlace", "JPEG"]),
+        lossless_bit_depth: 0,
+    },
+    // quality 100 でも VP8 の非可逆圧縮のため、可逆圧縮は webp-lossless で明示する
+    ImageFormat { name: "webp", extension: "webp", coder: "WEBP", encoder: Encoder::Convert(&["-quality", "100"]), lossless_bit_depth: 0 },
+    // 非可逆のWebPは常に4:2:0のため、間引き前の色を考慮して縮小する sharp YUV を比較する
+    ImageFormat {
+        name: "webp-sharp-yuv",
+        extension: "webp",
+        coder: "WEBP",
+        encoder: Encoder::Convert(&["-quality", "100", "-define", "webp:use-sharp-yuv=true"]),
+        lossless_bit_depth: 0,
+    },
+    // 可逆のWebPは8bitまで。完全に透明な画素のRGBも書き換えないよう exact を指定する
+    ImageFormat {
+        name: "webp-lossless",
+        extension: "webp",
+        coder: "WEBP",
+        encoder: Encoder::Convert(&["-define", "webp:lossless=true", "-define", "webp:exact=true"]),
+        lossless_bit_depth: 8,
+    },
+    ImageFormat { name: "avif", extension: "avif", coder: "AVIF", encoder: Encoder::Convert(&["-quality", "90"]), lossless_bit_depth: 0 },
+    // quality 100・色差の間引きなし（4:4:4）のAVIF。ImageMagickからは行列係数を指定できず、
+    // RGBからYCbCrへの変換で丸め誤差が出るため、可逆圧縮としては扱わない
+    ImageFormat {
+        name: "avif-lossless",
+        extension: "avif",
+        coder: "AVIF",
+        encoder: Encoder::Convert(&["-quality", "100", "-define", "heic:chroma=444"]),
+        lossless_bit_depth: 0,
+    },
+    ImageFormat { name: "jxl", extension: "jxl", coder: "JXL", encoder: Encoder::Convert(&["-quality", "90"]), lossless_bit_depth: 0 },
+    // JPEG XL は quality 100 で可逆圧縮（distance 0）になり、16bitも保存できる
+    ImageFormat { name: "jxl-lossless", extension: "jxl", coder: "JXL", encoder: Encoder::Convert(&["-quality", "100"]), lossless_bit_depth: 16 },
+    ImageFormat { name: "heic", extension: "heic", coder: "HEIC", encoder: Encoder::Convert(&["-quality", "90"]), lossless_bit_depth: 0 },
+    // QOIは8bitのRGB・RGBAのみ
+    ImageFormat { name: "qoi", extension: "qoi", coder: "QOI", encoder: Encoder::Convert(&[]), lossless_bit_depth: 8 },
+];
+
+/// `--formats` を省略した場合に比較するフォーマット
+///
+/// 標準的なImageMagickで扱えるものに限る。AVIF・JPEG XL・HEIC・QOIは対応したImageMagickが必要なため、指定した場合のみ比較する。
+const DEFAULT_FORMATS: &[&str] = &["png", "jpg", "webp"];
+
+/// `--lossless` で `--formats` を省略した場合に比較する、可逆圧縮のフォーマット
+const LOSSLESS_FORMATS: &[&str] = &["png", "png-optimized", "webp-lossless"];
+
+/// `--lossless` で `--formats` を省略した場合に、ImageMagickが読み書きできれば比較に加えるフォーマット
+pub const OPTIONAL_LOSSLESS_FORMATS: &[&str] = &["jxl-lossless"];
+
+/// インターレース・プログレッシブのフォーマットと、比較の基準にする通常のフォーマット
+const INTERLACED_BASELINES: &[(&str, &str)] = &[("png-interlaced", "png"), ("jpg-progressive", "jpg")];
+
+impl ImageFormat {
+    /// `image_000.jpg` や `image_000_avif-lossless.avif` のような出力ファイルのパス
+    pub fn file_path(&self, output_dir: &str, index: u32) -> String {
+        if self.name == self.extension {
+            format!("{}/image_{:03}.{}", output_dir, index, self.extension)
+        } else {
+            format!("{}/image_{:03}_{}.{}", output_dir, index, self.name, self.extension)
+        }
+    }
+
+    pub fn is_source(&self) -> bool {
+        self.name == SOURCE_NAME
+    }
+
+    /// インターレース・プログレッシブのフォーマットの場合は、同じ設定で通常の符号化をするフォーマットの名前
+    pub fn interlace_baseline(&self) -> Option<&'static str> {
+        INTERLACED_BASELINES.iter().find(|(name, _)| *name == self.name).map(|(_, baseline)| *baseline)
+    }
+
+    /// ビット深度 `bit_depth` の画像を、元画像と画素単位で一致するよう保存できるか
+    pub fn is_lossless(&self, bit_depth: u8) -> bool {
+        bit_depth <= self.lossless_bit_depth
+    }
+
+    /// 変換にImageMagick以外で必要な外部コマンド（`tools` での名前）
+    pub fn tool(&self) -> Option<&'static str> {
+        match self.encoder {
+            Encoder::Convert(_) => None,
+            Encoder::Oxipng(_) => Some("oxipng"),
+        }
+    }
+
+    /// `convert` に渡す `-quality` の値（品質を指定しないフォーマットでは `None`）
+    pub fn quality(&self) -> Option<u32> {
+        match self.encoder {
+            Encoder::Convert(args) => args.windows(2).find(|pair| pair[0] == "-quality").and_then(|pair| pair[1].parse().ok()),
+            Encoder::Oxipng(_) => None,
+        }
+    }
+
+    /// 元画像 `input` からこのフォーマットの `output` を作るコマンド
+    pub fn encode_command(&self, input: &str, output: &str) -> Command {
+        match self.encoder {
+            Encoder::Convert(args) => {
+                let mut command = imagemagick::command();
+                command.arg(input).args(args).arg(output);
+                command
+            },
+            Encoder::Oxipng(args) => {
+                let mut command = tools::command("oxipng");
+                command.args(args).arg("--out").arg(output).arg(input);
+                command
+            },
+        }
+    }
+
+    /// 元画像 `input` を幅 `width` 以下に縮小し、このフォーマットの `output` を作るコマンド（順に実行する）
+    ///
+    /// `filter` はImageMagickの `-filter` に渡すリサンプリングフィルタ。oxipng は縮小できないため、
+    /// 縮小したPNGを作ってから、そのファイルをその場で再圧縮する。
+    pub fn resize_commands(&self, input: &str, output: &str, filter: &str, width: u32) -> Vec<Command> {
+        let mut resize = imagemagick::command();
+        resize.arg(input).args(["-filter", filter, "-resize", &format!("{}x>", width)]);
+        match self.encoder {
+            Encoder::Convert(args) => {
+                resize.args(args).arg(output);
+                vec![resize]
+            },
+            Encoder::Oxipng(args) => {
+                resize.arg(output);
+                let mut optimize = tools::command("oxipng");
+                optimize.args(args).arg(output);
+                vec![resize, optimize]
+            },
+        }
+    }
+}
+
+pub fn source() -> &'static ImageFormat {
+    find(SOURCE_NAME).expect("元画像のフォーマットは一覧に含まれている")
+}
+
+pub fn default_formats() -> Vec<&'static ImageFormat> {
+    DEFAULT_FORMATS.iter().filter_map(|name| find(name)).collect()
+}
+
+pub fn lossless_formats() -> Vec<&'static ImageFormat> {
+    LOSSLESS_FORMATS.iter().filter_map(|name| find(name)).collect()
+}
+
+/// `OPTIONAL_LOSSLESS_FORMATS` のうち、ImageMagickが読み書きできるもの（外部コマンドを決めたあとに呼ぶ）
+pub fn supported_optional_lossless_formats() -> Vec<&'static ImageFormat> {
+    OPTIONAL_LOSSLESS_FORMATS.iter().filter_map(|name| find(name)).filter(|format| imagemagick::supports(format.coder)).collect()
+}
+
+pub fn find(name: &str) -> Option<&'static ImageFormat> {
+    FORMATS.iter().find(|format| format.name == name)
+}
+
+/// `jpg,webp,avif` のようなカンマ区切りの一覧を解釈する
+pub fn parse_list(value: &str) -> Result<Vec<&'static ImageFormat>, String> {
+    let mut formats = Vec::new();
+    for name in value.split(',').map(|name| name.trim().to_ascii_lowercase()).filter(|name| !name.is_empty()) {
+        let format = find(&name).ok_or_else(|| format!("未対応のフォーマットです: {} (対応: {})", name, names().join(", ")))?;
+        if !formats.iter().any(|f: &&ImageFormat| f.name == format.name) {
+            formats.push(format);
+        }
+    }
+
+    if formats.is_empty() {
+        return Err("--formats には1つ以上のフォーマットを指定してください".to_string());
+    }
+    Ok(formats)
+}
+
+/// 比較に必要なImageMagickの対応フォーマット（元画像のPNGと、各フォーマットの書き込み・検証のための読み込み）
+pub fn requirements(formats: &[&ImageFormat]) -> Vec<Requirement> {
+    std::iter::once(source())
+        .chain(formats.iter().copied())
+        .map(|format| Requirement { coder: format.coder, read: true, write: true })
+        .collect()
+}
+
+pub fn names() -> Vec<&'static str> {
+    FORMATS.iter().map(|format| format.name).collect()
+}
@@ -0,0 +1,369 @@
+/// ICCのプロファイル接続空間の白色点（D50）
+pub const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+/// 色空間の三原色と白色点（xy色度座標）、トーンカーブ
+///
+/// 外部のプロファイルファイルに頼らずに埋め込み用のICCプロファイルを用意できるよう、
+/// 行列とトーンカーブで表すRGBのプロファイル（v2、ディスプレイクラス）を組み立てる。
+#[derive(PartialEq)]
+pub struct RgbColorSpace {
+    /// `--color-spaces` で使う名前
+    pub name: &'static str,
+    pub description: &'static str,
+    pub red: (f64, f64),
+    pub green: (f64, f64),
+    pub blue: (f64, f64),
+    pub white: (f64, f64),
+    pub transfer: Transfer,
+}
+
+/// 符号化された値（0〜1）から線形な値への変換
+#[derive(Clone, Copy, PartialEq)]
+pub enum Transfer {
+    /// sRGBの区分的なカーブ
+    Srgb,
+    /// 単純なべき乗
+    Gamma(f64),
+}
+
+impl Transfer {
+    pub fn to_linear(self, value: f64) -> f64 {
+        match self {
+            Transfer::Srgb => {
+                if value <= 0.04045 {
+                    value / 12.92
+                } else {
+                    ((value + 0.055) / 1.055).powf(2.4)
+                }
+            },
+            Transfer::Gamma(gamma) => value.powf(gamma),
+        }
+    }
+}
+
+pub static SRGB: RgbColorSpace = RgbColorSpace {
+    name: "srgb",
+    description: "sRGB",
+    red: (0.64, 0.33),
+    green: (0.30, 0.60),
+    blue: (0.15, 0.06),
+    white: (0.3127, 0.3290),
+    transfer: Transfer::Srgb,
+};
+
+/// Display P3（DCI-P3の原色、D65、sRGBのトーンカーブ）
+pub static DISPLAY_P3: RgbColorSpace = RgbColorSpace {
+    name: "display-p3",
+    description: "Display P3",
+    red: (0.680, 0.320),
+    green: (0.265, 0.690),
+    blue: (0.150, 0.060),
+    white: (0.3127, 0.3290),
+    transfer: Transfer::Srgb,
+};
+
+/// Adobe RGB (1998)（ガンマは仕様どおり 563/256 ≒ 2.2）
+pub static ADOBE_RGB: RgbColorSpace = RgbColorSpace {
+    name: "adobe-rgb",
+    description: "Adobe RGB (1998)",
+    red: (0.64, 0.33),
+    green: (0.21, 0.71),
+    blue: (0.15, 0.06),
+    white: (0.3127, 0.3290),
+    transfer: Transfer::Gamma(563.0 / 256.0),
+};
+
+pub static COLOR_SPACES: &[&RgbColorSpace] = &[&SRGB, &DISPLAY_P3, &ADOBE_RGB];
+
+/// `srgb,display-p3` のような色空間の一覧を解釈する（`all` で全て）
+pub fn parse_color_spaces(value: &str) -> Result<Vec<&'static RgbColorSpace>, String> {
+    if value.trim().eq_ignore_ascii_case("all") {
+        return Ok(COLOR_SPACES.to_vec());
+    }
+
+    let mut color_spaces: Vec<&'static RgbColorSpace> = Vec::new();
+    for name in value.split(',').map(|name| name.trim().to_ascii_lowercase()).filter(|name| !name.is_empty()) {
+        let color_space = COLOR_SPACES.iter().copied().find(|space| space.name == name).ok_or_else(|| {
+            format!("未対応の色空間です: {} (対応: {}, all)", name, COLOR_SPACES.iter().map(|space| space.name).collect::<Vec<_>>().join(", "))
+        })?;
+        if !color_spaces.contains(&color_space) {
+            color_spaces.push(color_space);
+        }
+    }
+
+    if color_spaces.is_empty() {
+        return Err("--color-spaces には1つ以上の色空間を指定してください".to_string());
+    }
+    Ok(color_spaces)
+}
+
+impl RgbColorSpace {
+    /// 線形なRGBから（この色空間の白色点での）XYZへの変換行列
+    pub fn rgb_to_xyz(&self) -> [[f64; 3]; 3] {
+        let to_xyz = |(x, y): (f64, f64)| [x / y, 1.0, (1.0 - x - y) / y];
+        let primaries = [to_xyz(self.red), to_xyz(self.green), to_xyz(self.blue)];
+        let white = to_xyz(self.white);
+
+        // 各原色の強さ S を、S の重み付き和が白色点になるように決める
+        let columns = transpose(primaries);
+        let scale = multiply_vector(invert(columns), white);
+        let mut matrix = columns;
+        for row in &mut matrix {
+            for (value, scale) in row.iter_mut().zip(scale) {
+                *value *= scale;
+            }
+        }
+        matrix
+    }
+
+    /// 白色点をD50に順応させた（Bradford変換）、線形なRGBからXYZへの変換行列
+    pub fn rgb_to_xyz_d50(&self) -> [[f64; 3]; 3] {
+        let (x, y) = self.white;
+        let white = [x / y, 1.0, (1.0 - x - y) / y];
+        multiply(bradford(white, D50), self.rgb_to_xyz())
+    }
+
+    /// この色空間のICCプロファイル
+    pub fn profile(&self) -> Vec<u8> {
+        let (x, y) = self.white;
+        let matrix = self.rgb_to_xyz_d50();
+        let column = |index: usize| xyz_tag([matrix[0][index], matrix[1][index], matrix[2][index]]);
+        let curve = curve_tag(self.transfer);
+
+        let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+            (b"desc", description_tag(self.description)),
+            (b"cprt", text_tag("No copyright, use freely")),
+            (b"wtpt", xyz_tag([x / y, 1.0, (1.0 - x - y) / y])),
+            (b"rXYZ", column(0)),
+            (b"gXYZ", column(1)),
+            (b"bXYZ", column(2)),
+            (b"rTRC", curve.clone()),
+            (b"gTRC", curve.clone()),
+            (b"bTRC", curve),
+        ];
+
+        // ヘッダー（128バイト）とタグ表のあとに、4バイト境界にそろえたタグのデータを並べる
+        let table_size = 4 + tags.len() * 12;
+        let mut data = Vec::new();
+        let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+        for (signature, tag) in &tags {
+            let offset = 128 + table_size + data.len();
+            table.extend_from_slice(*signature);
+            table.extend_from_slice(&(offset as u32).to_be_bytes());
+            table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+            data.extend_from_slice(tag);
+            while data.len() % 4 != 0 {
+                data.push(0);
+            }
+        }
+
+        let size = 128 + table_size + data.len();
+        let mut profile = Vec::with_capacity(size);
+        profile.extend_from_slice(&(size as u32).to_be_bytes());
+        profile.extend_from_slice(&[0; 4]); // CMM
+        profile.extend_from_slice(&[0x02, 0x10, 0x00, 0x00]); // バージョン 2.1
+        profile.extend_from_slice(b"mntr");
+        profile.extend_from_slice(b"RGB ");
+        profile.extend_from_slice(b"XYZ ");
+        for value in [2024u16, 1, 1, 0, 0, 0] {
+            profile.extend_from_slice(&value.to_be_bytes());
+        }
+        profile.extend_from_slice(b"acsp");
+        profile.extend_from_slice(&[0; 24]); // プラットフォーム、フラグ、製造元、機種、属性
+        profile.extend_from_slice(&[0; 4]); // レンダリングインテント（知覚的）
+        profile.extend_from_slice(&xyz_numbers(D50));
+        profile.extend_from_slice(&[0; 48]); // 作成者、プロファイルID、予約領域
+        profile.extend_from_slice(&table);
+        profile.extend_from_slice(&data);
+        profile
+    }
+}
+
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz_numbers(xyz: [f64; 3]) -> Vec<u8> {
+    xyz.iter().flat_map(|&value| s15_fixed16(value)).collect()
+}
+
+fn xyz_tag(xyz: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    tag.extend(xyz_numbers(xyz));
+    tag
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+/// v2の `textDescriptionType`（ASCIIのみで、Unicode・ScriptCodeは空）
+fn description_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    tag.extend_from_slice(&[0; 8]); // Unicodeの言語コードと文字数
+    tag.extend_from_slice(&[0; 3]); // ScriptCodeのコードと文字数
+    tag.extend_from_slice(&[0; 67]);
+    tag
+}
+
+fn curve_tag(transfer: Transfer) -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    match transfer {
+        // 単純なべき乗は指数のみ（u8Fixed8）で表せる
+        Transfer::Gamma(gamma) => {
+            tag.extend_from_slice(&1u32.to_be_bytes());
+            tag.extend_from_slice(&((gamma * 256.0).round() as u16).to_be_bytes());
+        },
+        Transfer::Srgb => {
+            const ENTRIES: u32 = 1024;
+            tag.extend_from_slice(&ENTRIES.to_be_bytes());
+            for i in 0..ENTRIES {
+                let linear = transfer.to_linear(i as f64 / (ENTRIES - 1) as f64);
+                tag.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+            }
+        },
+    }
+    tag
+}
+
+/// 白色点 `from` から `to` への Bradford 色順応行列
+fn bradford(from: [f64; 3], to: [f64; 3]) -> [[f64; 3]; 3] {
+    const CONE: [[f64; 3]; 3] = [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ];
+    let from_cone = multiply_vector(CONE, from);
+    let to_cone = multiply_vector(CONE, to);
+    let mut scale = [[0.0; 3]; 3];
+    for i in 0..3 {
+        scale[i][i] = to_cone[i] / from_cone[i];
+    }
+    multiply(invert(CONE), multiply(scale, CONE))
+}
+
+pub fn multiply(a: [[f64; 3]; 3], b: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut result = [[0.0; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+pub fn multiply_vector(matrix: [[f64; 3]; 3], vector: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row[0] * vector[0] + row[1] * vector[1] + row[2] * vector[2])
+}
+
+fn transpose(matrix: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut result = [[0.0; 3]; 3];
+    for (i, row) in matrix.iter().enumerate() {
+        for (j, &value) in row.iter().enumerate() {
+            result[j][i] = value;
+        }
+    }
+    result
+}
+
+pub fn invert(m: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let cofactor = |r1: usize, r2: usize, c1: usize, c2: usize| m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1];
+    let adjugate = [
+        [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+        [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+        [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+    ];
+    let determinant = m[0][0] * adjugate[0][0] + m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+    adjugate.map(|row| row.map(|value| value / determinant))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_matrix_near(actual: [[f64; 3]; 3], expected: [[f64; 3]; 3], tolerance: f64) {
+        for (actual_row, expected_row) in actual.iter().zip(&expected) {
+            for (a, e) in actual_row.iter().zip(expected_row) {
+                assert!((a - e).abs() < tolerance, "{:?} と {:?} が一致しない", actual, expected);
+            }
+        }
+    }
+
+    fn white_xyz(color_space: &RgbColorSpace) -> [f64; 3] {
+        let (x, y) = color_space.white;
+        [x / y, 1.0, (1.0 - x - y) / y]
+    }
+
+    #[test]
+    fn srgb_matrix_matches_iec_61966_2_1() {
+        let expected = [
+            [0.4124, 0.3576, 0.1805],
+            [0.2126, 0.7152, 0.0722],
+            [0.0193, 0.1192, 0.9505],
+        ];
+        assert_matrix_near(SRGB.rgb_to_xyz(), expected, 1e-4);
+    }
+
+    #[test]
+    fn srgb_d50_matrix_matches_bradford_adapted_reference() {
+        // Lindbloom の Bradford 変換後の sRGB 行列（D50 の値の違いの分だけ許容誤差を大きくする）
+        let expected = [
+            [0.4360747, 0.3850649, 0.1430804],
+            [0.2225045, 0.7168786, 0.0606169],
+            [0.0139322, 0.0971045, 0.7141733],
+        ];
+        assert_matrix_near(SRGB.rgb_to_xyz_d50(), expected, 1e-3);
+    }
+
+    #[test]
+    fn white_maps_to_d50_after_adaptation() {
+        for color_space in COLOR_SPACES {
+            let white = multiply_vector(color_space.rgb_to_xyz_d50(), [1.0, 1.0, 1.0]);
+            for (value, expected) in white.iter().zip(D50) {
+                assert!((value - expected).abs() < 1e-9, "{}: 白が {:?}", color_space.name, white);
+            }
+        }
+    }
+
+    #[test]
+    fn bradford_between_same_white_is_identity() {
+        let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        assert_matrix_near(bradford(D50, D50), identity, 1e-12);
+        let d65 = white_xyz(&SRGB);
+        assert_matrix_near(multiply(bradford(D50, d65), bradford(d65, D50)), identity, 1e-12);
+    }
+
+    #[test]
+    fn invert_gives_inverse_matrix() {
+        let matrix = SRGB.rgb_to_xyz();
+        let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        assert_matrix_near(multiply(matrix, invert(matrix)), identity, 1e-12);
+    }
+
+    #[test]
+    fn profile_has_consistent_header_and_tag_table() {
+        for color_space in COLOR_SPACES {
+            let profile = color_space.profile();
+            let read_u32 = |offset: usize| u32::from_be_bytes(profile[offset..offset + 4].try_into().unwrap()) as usize;
+
+            assert_eq!(read_u32(0), profile.len(), "{}: ヘッダーのサイズ", color_space.name);
+            assert_eq!(&profile[36..40], b"acsp", "{}: シグネチャ", color_space.name);
+            assert_eq!(&profile[12..20], b"mntrRGB ", "{}: クラスと色空間", color_space.name);
+
+            let count = read_u32(128);
+            assert_eq!(count, 9, "{}: タグの数", color_space.name);
+            for index in 0..count {
+                let entry = 132 + index * 12;
+                let (offset, size) = (read_u32(entry + 4), read_u32(entry + 8));
+                assert_eq!(offset % 4, 0, "{}: タグ {} の位置", color_space.name, index);
+                assert!(offset + size <= profile.len(), "{}: タグ {} がファイルの外にある", color_space.name, index);
+            }
+        }
+    }
+}
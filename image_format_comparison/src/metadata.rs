@@ -0,0 +1,234 @@
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+use benchmark_common::imagemagick;
+use benchmark_common::pool;
+
+use crate::formats::{self, ImageFormat};
+use crate::icc::RgbColorSpace;
+
+/// 元画像に埋め込むメタデータのブロック
+#[derive(Clone, Copy, PartialEq)]
+pub enum MetadataBlock {
+    Exif,
+    Icc,
+    Xmp,
+}
+
+pub static BLOCKS: &[MetadataBlock] = &[MetadataBlock::Exif, MetadataBlock::Icc, MetadataBlock::Xmp];
+
+impl MetadataBlock {
+    /// `--metadata` で使う名前（ImageMagickのプロファイル名と同じ）
+    pub fn name(self) -> &'static str {
+        match self {
+            MetadataBlock::Exif => "exif",
+            MetadataBlock::Icc => "icc",
+            MetadataBlock::Xmp => "xmp",
+        }
+    }
+
+    /// 埋め込むブロックの内容（ICCプロファイルは画素値の色空間 `color_space` のもの）
+    pub fn contents(self, color_space: &RgbColorSpace) -> Vec<u8> {
+        match self {
+            MetadataBlock::Exif => exif_block(),
+            MetadataBlock::Icc => color_space.profile(),
+            MetadataBlock::Xmp => XMP_PACKET.as_bytes().to_vec(),
+        }
+    }
+
+    /// 作業ディレクトリに書き出すブロックのファイル名（例: `metadata.exif`）
+    pub fn file_path(self, output_dir: &str) -> String {
+        format!("{}/metadata.{}", output_dir, self.name())
+    }
+
+    /// `-profile` に渡す引数（例: `exif:images_run_1/metadata.exif`）
+    fn profile_arg(self, output_dir: &str) -> String {
+        format!("{}:{}", self.name(), self.file_path(output_dir))
+    }
+}
+
+/// `exif,icc,xmp` のようなカンマ区切りの一覧を解釈する（`all` で全て）
+pub fn parse_list(value: &str) -> Result<Vec<MetadataBlock>, String> {
+    if value.trim().eq_ignore_ascii_case("all") {
+        return Ok(BLOCKS.to_vec());
+    }
+
+    let mut blocks = Vec::new();
+    for name in value.split(',').map(|name| name.trim().to_ascii_lowercase()).filter(|name| !name.is_empty()) {
+        let block = BLOCKS.iter().copied().find(|block| block.name() == name).ok_or_else(|| {
+            format!("未対応のメタデータです: {} (対応: {}, all)", name, BLOCKS.iter().map(|block| block.name()).collect::<Vec<_>>().join(", "))
+        })?;
+        if !blocks.contains(&block) {
+            blocks.push(block);
+        }
+    }
+
+    if blocks.is_empty() {
+        return Err("--metadata には1つ以上のメタデータを指定してください".to_string());
+    }
+    Ok(blocks)
+}
+
+/// 生成済みの元画像（PNG）全てに、指定したブロックを埋め込む
+pub async fn embed(
+    output_dir: &str,
+    blocks: &[MetadataBlock],
+    color_space: &RgbColorSpace,
+    count: u32,
+    jobs: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let block_paths: Vec<String> = blocks.iter().map(|block| block.file_path(output_dir)).collect();
+    for (block, path) in blocks.iter().zip(&block_paths) {
+        fs::write(path, block.contents(color_space))?;
+    }
+
+    let output_dir = Arc::new(output_dir.to_string());
+    let blocks = Arc::new(blocks.to_vec());
+    let result = pool::for_each_image(count, jobs, move |i| {
+        let output_dir = Arc::clone(&output_dir);
+        let blocks = Arc::clone(&blocks);
+
+        async move {
+            let path = formats::source().file_path(&output_dir, i);
+            let mut command = imagemagick::command();
+            command.arg(&path);
+            for block in blocks.iter() {
+                command.arg("-profile").arg(block.profile_arg(&output_dir));
+            }
+            let status = command
+                .args(["-define", "png:exclude-chunk=date,time"])
+                .arg(&path)
+                .status()
+                .await
+                .map_err(|e| format!("メタデータ埋め込みコマンド実行エラー: {}", e))?;
+
+            if !status.success() {
+                return Err(format!("メタデータの埋め込みに失敗しました: {}", path));
+            }
+            Ok(())
+        }
+    })
+    .await;
+
+    // 埋め込みに失敗した場合も、ブロックのファイルは残さない
+    for path in &block_paths {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+/// 画像からブロックを取り出し、残っていればそのバイト数を返す
+pub async fn block_size(path: &str, block: MetadataBlock) -> Result<Option<u64>, String> {
+    let output = imagemagick::command()
+        .args([path, &format!("{}:-", block.name())])
+        .output()
+        .await
+        .map_err(|e| format!("メタデータ取り出しコマンド実行エラー: {}", e))?;
+
+    // ブロックがない場合、ImageMagickはエラー終了するか何も出力しない
+    if !output.status.success() || output.stdout.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(output.stdout.len() as u64))
+}
+
+/// 全画像について各ブロックが残っているか調べ、ブロックごとの全画像の合計バイト数を返す
+///
+/// 1枚でもブロックが失われていた場合、そのブロックは `None` になる。
+pub async fn surviving_block_sizes(
+    output_dir: &str,
+    format: &'static ImageFormat,
+    blocks: &[MetadataBlock],
+    count: u32,
+    jobs: usize,
+) -> Result<Vec<Option<u64>>, Box<dyn std::error::Error>> {
+    let output_dir = Arc::new(output_dir.to_string());
+    let blocks = Arc::new(blocks.to_vec());
+    let totals = Arc::new(Mutex::new(vec![Some(0); blocks.len()]));
+    let totals_clone = Arc::clone(&totals);
+
+    pool::for_each_image(count, jobs, move |i| {
+        let output_dir = Arc::clone(&output_dir);
+        let blocks = Arc::clone(&blocks);
+        let totals = Arc::clone(&totals_clone);
+
+        async move {
+            let path = format.file_path(&output_dir, i);
+            for (index, &block) in blocks.iter().enumerate() {
+                let size = block_size(&path, block).await?;
+                let mut totals = totals.lock().unwrap();
+                totals[index] = totals[index].zip(size).map(|(total, size)| total + size);
+            }
+            Ok(())
+        }
+    })
+    .await?;
+
+    let totals = std::mem::take(&mut *totals.lock().unwrap());
+    Ok(totals)
+}
+
+/// 埋め込むXMP（撮影者・著作権・説明などを持つ典型的なパケット）
+const XMP_PACKET: &str = concat!(
+    // 先頭はUTF-8のBOMを値に持つ決まった形式
+    "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+    r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:photoshop="http://ns.adobe.com/photoshop/1.0/">
+   <dc:creator><rdf:Seq><rdf:li>Benchmark Photographer</rdf:li></rdf:Seq></dc:creator>
+   <dc:rights><rdf:Alt><rdf:li xml:lang="x-default">Copyright 2024 Benchmark</rdf:li></rdf:Alt></dc:rights>
+   <dc:description><rdf:Alt><rdf:li xml:lang="x-default">Random noise image for metadata benchmark</rdf:li></rdf:Alt></dc:description>
+   <dc:subject><rdf:Bag><rdf:li>benchmark</rdf:li><rdf:li>noise</rdf:li></rdf:Bag></dc:subject>
+   <xmp:CreatorTool>image_format_comparison</xmp:CreatorTool>
+   <xmp:CreateDate>2024-01-01T00:00:00Z</xmp:CreateDate>
+   <photoshop:City>Tokyo</photoshop:City>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#
+);
+
+/// 埋め込むEXIF（`Exif\0\0` に続くビッグエンディアンのTIFF構造で、IFD0にカメラ情報の文字列を持つ）
+fn exif_block() -> Vec<u8> {
+    const ASCII: u16 = 2;
+    // タグ番号の昇順
+    let entries: &[(u16, &str)] = &[
+        (0x010F, "Benchmark Camera Co."),
+        (0x0110, "Benchmark Model 1"),
+        (0x0131, "image_format_comparison"),
+        (0x0132, "2024:01:01 00:00:00"),
+        (0x013B, "Benchmark Photographer"),
+        (0x8298, "Copyright 2024 Benchmark"),
+    ];
+
+    // TIFFヘッダー（8バイト）、エントリ数、エントリ（12バイトずつ）、次のIFDへのオフセットのあとに文字列を置く
+    let mut data_offset = 8 + 2 + entries.len() * 12 + 4;
+    let mut ifd = Vec::new();
+    let mut data = Vec::new();
+    ifd.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+    for &(tag, value) in entries {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        ifd.extend_from_slice(&tag.to_be_bytes());
+        ifd.extend_from_slice(&ASCII.to_be_bytes());
+        ifd.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        // 4バイト以下の値はオフセットの位置に直接入れる
+        if bytes.len() <= 4 {
+            bytes.resize(4, 0);
+            ifd.extend_from_slice(&bytes);
+        } else {
+            ifd.extend_from_slice(&(data_offset as u32).to_be_bytes());
+            data_offset += bytes.len();
+            data.extend_from_slice(&bytes);
+        }
+    }
+    ifd.extend_from_slice(&0u32.to_be_bytes());
+
+    let mut block = b"Exif\0\0MM\0\x2a\0\0\0\x08".to_vec();
+    block.extend_from_slice(&ifd);
+    block.extend_from_slice(&data);
+    block
+}
@@ -1,51 +1,182 @@
+mod cli;
+mod color;
+mod derivative;
+mod formats;
+mod icc;
+mod metadata;
+mod variant;
+mod verify;
+
 use std::fs;
-use std::process::Command;
-use csv::Writer;
 use serde::Serialize;
 use rand::{thread_rng, Rng};
-use tokio::task::JoinSet;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::env;
+use std::path::Path;
+use std::time::Instant;
+use cli::Options;
+use formats::ImageFormat;
+use manifest::RunManifest;
+use metadata::MetadataBlock;
+use benchmark_common::{imagemagick, interrupt, manifest, pool, tools};
+use benchmark_common::output::{self, ResultWriter};
+use variant::ImageVariant;
 
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 struct ImageStats {
+    run_id: String,
     run_number: u32,
     format: String,
+    width: u32,
+    height: u32,
+    channels: u8,
+    bit_depth: u8,
+    /// RGBA画像の透明度の付け方（透明度のない画像では空）
+    alpha_pattern: Option<String>,
+    /// 画素値の色空間（`srgb` / `display-p3` / `adobe-rgb`）
+    color_space: String,
     total_size: u64,
     average_size: f64,
     min_size: u64,
     max_size: u64,
     median_size: f64,
+    /// 1枚あたりの平均デコード時間（秒、`convert` の起動を含む）
+    average_decode_seconds: f64,
+    lossless_verified: bool,
+    /// 元画像のビット深度でのチャンネル値の最大絶対誤差
+    max_channel_error: u16,
+    /// RGBチャンネルでの元画像とのPSNR（dB、全画像が元画像と一致した場合は空）
+    psnr: Option<f64>,
+    /// 全画像が透明度のチャンネルを保持したか（以下3列は透明度のない画像では空）
+    alpha_preserved: Option<bool>,
+    /// 透明度のチャンネルのみの最大絶対誤差
+    max_alpha_error: Option<u16>,
+    /// 透明度を除いた同じ画像と比べて増えた合計サイズ（バイト）
+    alpha_size_cost: Option<i64>,
+    /// 埋め込んだEXIFが全画像で残ったか（以下6列は `--metadata` で埋め込まなかったブロックでは空）
+    exif_preserved: Option<bool>,
+    /// 残ったEXIFの全画像の合計バイト数
+    exif_bytes: Option<u64>,
+    icc_preserved: Option<bool>,
+    icc_bytes: Option<u64>,
+    xmp_preserved: Option<bool>,
+    xmp_bytes: Option<u64>,
+    /// `--strip` でメタデータを除いた場合の合計サイズ（以下2列は `--strip` を指定しない場合は空）
+    stripped_total_size: Option<u64>,
+    /// メタデータによって増えた合計サイズ（バイト）
+    metadata_size_cost: Option<i64>,
+    /// 全画像がICCプロファイルを保持したか（以下3列は `--color-spaces` を指定しない場合は空）
+    profile_preserved: Option<bool>,
+    /// カラーマネジメント後の元画像との色差（ΔE2000）の全画素の平均
+    mean_delta_e: Option<f64>,
+    /// 色差（ΔE2000）の最大
+    max_delta_e: Option<f64>,
+}
+
+/// `--details` で記録する、画像ごとの結果
+#[derive(Serialize, Default)]
+struct ImageDetail {
+    run_id: String,
+    run_number: u32,
+    image_index: u32,
+    seed: u64,
+    format: String,
+    width: u32,
+    height: u32,
+    channels: u8,
+    bit_depth: u8,
+    alpha_pattern: Option<String>,
+    color_space: String,
+    /// 変換時に指定した品質（品質を指定しないフォーマットでは空）
+    quality: Option<u32>,
+    size: u64,
+    /// 元画像のPNGは変換していないため空
+    encode_seconds: Option<f64>,
+    decode_seconds: f64,
+}
+
+/// `--scaling` で記録する、ワーカー数ごとの変換スループット
+#[derive(Serialize, Default)]
+struct ScalingStats {
+    run_id: String,
+    run_number: u32,
+    format: String,
+    width: u32,
+    height: u32,
+    channels: u8,
+    bit_depth: u8,
+    alpha_pattern: Option<String>,
+    color_space: String,
+    workers: usize,
+    image_count: u32,
+    elapsed_seconds: f64,
+    images_per_second: f64,
+    speedup: f64,
+}
+
+/// `--derivatives` で記録する、フォーマット・フィルタごとの派生画像の合計サイズとCPU時間
+#[derive(Serialize, Default)]
+struct DerivativeStats {
+    run_id: String,
+    run_number: u32,
+    format: String,
+    width: u32,
+    height: u32,
+    channels: u8,
+    bit_depth: u8,
+    alpha_pattern: Option<String>,
+    color_space: String,
+    filter: String,
+    /// 作った派生画像の幅（元画像より大きい幅は除く）
+    ladder: String,
+    image_count: u32,
+    derivative_count: u32,
+    /// 元画像（PNG）の合計サイズ
+    original_total_size: u64,
+    /// 全派生画像の合計サイズ
+    derivative_total_size: u64,
+    /// 元画像1枚あたりの保存容量（元画像とその派生画像の合計、バイト）
+    storage_per_original: f64,
+    /// 全派生画像の作成に使ったCPU時間（子プロセスのユーザー＋システム、秒）
+    cpu_seconds: f64,
+    cpu_seconds_per_original: f64,
+    elapsed_seconds: f64,
 }
 
 fn print_help() {
     println!("画像フォーマット比較ベンチマーク（テスト版）");
-    println!("");
+    println!();
     println!("使用方法:");
     println!("  {} [画像枚数] [ラウンド数]", env::args().next().unwrap_or_else(|| "test_program".to_string()));
-    println!("");
+    println!();
     println!("引数:");
     println!("  画像枚数    各ラウンドで生成する画像の枚数 (デフォルト: 5)");
     println!("  ラウンド数  ベンチマークの実行回数 (デフォルト: 1)");
-    println!("");
+    println!();
     println!("オプション:");
-    println!("  -h, --help  このヘルプメッセージを表示");
-    println!("");
+    cli::print_options_help();
+    println!();
     println!("例:");
     println!("  {}           # デフォルト: 5枚、1ラウンド", env::args().next().unwrap_or_else(|| "test_program".to_string()));
     println!("  {} 10        # 10枚、1ラウンド", env::args().next().unwrap_or_else(|| "test_program".to_string()));
     println!("  {} 20 2      # 20枚、2ラウンド", env::args().next().unwrap_or_else(|| "test_program".to_string()));
+    println!("  {} 5 1 --output-format ndjson  # JSON Lines で出力", env::args().next().unwrap_or_else(|| "test_program".to_string()));
+    println!("  {} 5 1 --scaling --jobs 4      # 1〜4ワーカーでの変換スループット", env::args().next().unwrap_or_else(|| "test_program".to_string()));
+    println!("  {} 5 1 --lossless              # 可逆圧縮の比較と画素一致の確認", env::args().next().unwrap_or_else(|| "test_program".to_string()));
+    println!("  {} 2 1 --derivatives --ladder 512,256 # 派生画像の保存容量とCPU時間", env::args().next().unwrap_or_else(|| "test_program".to_string()));
 }
 
-fn parse_args() -> Result<(u32, u32), Box<dyn std::error::Error>> {
+fn parse_args() -> Result<(u32, u32, Options), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
     
     // ヘルプの表示
-    if args.len() > 1 && (args[1] == "--help" || args[1] == "-h") {
+    if args.iter().skip(1).any(|arg| arg == "--help" || arg == "-h") {
         print_help();
         std::process::exit(0);
     }
     
+    let (options, args) = cli::split_options(&args)?;
+    
     let image_count = if args.len() > 1 {
         args[1].parse::<u32>()
             .map_err(|_| "画像枚数は正の整数で指定してください")?
@@ -68,175 +199,671 @@ fn parse_args() -> Result<(u32, u32), Box<dyn std::error::Error>> {
         return Err("ラウンド数は1以上で指定してください".into());
     }
     
-    Ok((image_count, rounds))
+    Ok((image_count, rounds, options))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let (image_count, rounds) = parse_args()?;
+    let (image_count, rounds, mut options) = parse_args()?;
+    interrupt::install()?;
+    
+    // 外部コマンドのパスとバージョンを決める（--tool、環境変数 BENCH_TOOL_<名前>、PATH の順）
+    tools::resolve(cli::TOOLS, &options.tools)?;
+    
+    // ImageMagickがJPEG XLを読み書きできれば、--lossless の既定の比較に JXL-LOSSLESS も加える
+    if options.lossless_default {
+        options.formats.extend(formats::supported_optional_lossless_formats());
+    }
+    
+    // 画像を生成する前に、ImageMagickが全フォーマットを読み書きできるか、oxipng などの外部コマンドがあるか確認
+    imagemagick::preflight(&formats::requirements(&options.formats))?;
+    for format in &options.formats {
+        if let Some(tool) = format.tool() {
+            tools::require(tool, format.name)?;
+        }
+    }
+    
+    if options.scaling {
+        return run_scaling_benchmark(image_count, rounds, options).await;
+    }
+    if options.derivatives {
+        return run_derivative_benchmark(image_count, rounds, options).await;
+    }
     
     println!("画像フォーマット比較ベンチマーク（テスト版）開始: {}枚の画像で{}ラウンド実行", image_count, rounds);
+    println!("並列数: {}", options.jobs);
+    println!("フォーマット: {}", format_names(&options.formats));
+    println!("画像: {}", variant_labels(&options.variants));
     
-    let mut csv_writer = Writer::from_path("image_format_comparison_test_results.csv")?;
+    let seed = options.seed.unwrap_or_else(|| thread_rng().r#gen());
+    println!("シード: {}", seed);
     
-    for run in 1..=rounds {
+    let output_path = options.output.clone()
+        .unwrap_or_else(|| output::default_path("image_format_comparison_test_results", options.output_format));
+    let mut result_writer = ResultWriter::create::<ImageStats>(&output_path, options.output_format, options.append)?;
+    let details_path = output::details_path(&output_path);
+    let mut details_writer = if options.details {
+        Some(ResultWriter::create::<ImageDetail>(&details_path, options.output_format, options.append)?)
+    } else {
+        None
+    };
+    
+    // 実行環境を結果ファイルと同じ場所に記録
+    let manifest = RunManifest::collect(&output_path, Path::new("."), tools::resolved());
+    let manifest_path = manifest.write_next_to(&output_path, options.append)?;
+    println!("実行ID: {}", manifest.run_id);
+    
+    'rounds: for run in 1..=rounds {
+        if interrupt::is_interrupted() {
+            break;
+        }
+        
         println!("実行回数: {}/{}（テスト）", run, rounds);
         
         let output_dir = format!("test_images_run_{}", run);
         fs::create_dir_all(&output_dir)?;
         
-        // ランダムなPNG画像を生成（4並列）
-        generate_random_png_images_parallel(&output_dir, image_count).await?;
-        println!("PNG画像{}枚を生成しました", image_count);
+        // 寸法・色の種類・ビット深度の組み合わせごとに、画像の生成から統計までを行う
+        for variant in &options.variants {
+            // ランダムなPNG画像を生成（--jobs 並列）
+            generate_random_png_images_parallel(&output_dir, variant, image_count, seed, run, options.jobs).await?;
+            println!("PNG画像{}枚を生成しました（{}）", image_count, variant.label());
+            
+            // メタデータを埋め込む（--jobs 並列）
+            // 広色域の画像は、画素値の色空間を示すICCプロファイルを必ず埋め込む
+            let mut blocks = options.metadata.clone();
+            if variant.is_wide_gamut() && !blocks.contains(&MetadataBlock::Icc) {
+                blocks.push(MetadataBlock::Icc);
+            }
+            if !blocks.is_empty() {
+                metadata::embed(&output_dir, &blocks, variant.color_space, image_count, options.jobs).await?;
+                println!("メタデータを埋め込みました: {}", metadata_names(&blocks));
+            }
+            
+            // PNG -> 各フォーマットに変換（--jobs 並列）
+            let mut encode_seconds = Vec::new();
+            for format in &options.formats {
+                if format.is_source() {
+                    encode_seconds.push(None);
+                    continue;
+                }
+                encode_seconds.push(Some(convert_images_parallel(&output_dir, format, image_count, options.jobs).await?));
+                println!("{}に変換しました", format.name.to_uppercase());
+            }
+            
+            // 各フォーマットをデコードし、元画像と画素単位で比較（--jobs 並列）
+            // 元画像のPNGも、デコード時間を他のフォーマットと比べられるよう同じようにデコードする
+            let mut comparisons = Vec::new();
+            for format in &options.formats {
+                comparisons.push(verify::compare_pixels(&output_dir, format, variant.bit_depth, variant.alpha().is_some(), image_count, options.jobs).await?);
+            }
+            
+            // カラーマネジメントしたうえで、元画像との色差を測る（--jobs 並列）
+            let mut color_differences = Vec::new();
+            if options.delta_e {
+                for format in &options.formats {
+                    color_differences.push(color::compare_colors(&output_dir, format, variant.color_space, image_count, options.jobs).await?);
+                }
+            }
+            
+            // 透明度のある画像は、透明度を除いた同じ画像も変換してサイズを比べる（--jobs 並列）
+            let opaque_totals = match variant.alpha() {
+                Some(_) => Some(derived_total_sizes(&output_dir, "opaque", &["-alpha", "off"], &options.formats, image_count, options.jobs).await?),
+                None => None,
+            };
+            
+            // 埋め込んだメタデータが変換後も残っているか調べる（--jobs 並列）
+            let mut block_sizes = Vec::new();
+            if !options.metadata.is_empty() {
+                for format in &options.formats {
+                    block_sizes.push(metadata::surviving_block_sizes(&output_dir, format, &options.metadata, image_count, options.jobs).await?);
+                }
+            }
+            
+            // --strip ではメタデータを除いた同じ画像も変換してサイズを比べる（--jobs 並列）
+            let stripped_totals = if options.strip {
+                Some(derived_total_sizes(&output_dir, "stripped", &["-strip"], &options.formats, image_count, options.jobs).await?)
+            } else {
+                None
+            };
+            
+            // 中断された場合は途中までの画像で統計を取らずに終了処理へ進む
+            if interrupt::is_interrupted() {
+                break 'rounds;
+            }
+            
+            // 各形式の統計を計算して出力
+            let source_total = calculate_stats(&output_dir, &manifest.run_id, formats::source(), variant, run, image_count)?.total_size;
+            let mut not_lossless = Vec::new();
+            for (index, (format, comparison)) in options.formats.iter().zip(&comparisons).enumerate() {
+                let max_error = comparison.max_channel_error;
+                let mut stats = calculate_stats(&output_dir, &manifest.run_id, format, variant, run, image_count)?;
+                stats.lossless_verified = max_error == 0;
+                stats.max_channel_error = max_error;
+                stats.psnr = comparison.psnr;
+                stats.average_decode_seconds = average(&comparison.decode_seconds);
+                
+                println!(
+                    "{}: 元画像との最大誤差 {}、PSNR {}、平均デコード時間 {:.4} 秒",
+                    stats.format,
+                    max_error,
+                    comparison.psnr.map_or("一致".to_string(), |psnr| format!("{:.2} dB", psnr)),
+                    stats.average_decode_seconds
+                );
+                // インターレース・プログレッシブのフォーマットは、通常の符号化と比べたサイズとデコード時間の差を表示する
+                if let Some(baseline_index) = options.formats.iter().position(|f| Some(f.name) == format.interlace_baseline()) {
+                    let baseline_total = calculate_stats(&output_dir, &manifest.run_id, options.formats[baseline_index], variant, run, image_count)?.total_size;
+                    let baseline_decode = average(&comparisons[baseline_index].decode_seconds);
+                    println!(
+                        "{}: {}比 サイズ {:+.1}%、デコード時間 {:+.1}%",
+                        stats.format,
+                        options.formats[baseline_index].name.to_uppercase(),
+                        (stats.total_size as f64 / baseline_total as f64 - 1.0) * 100.0,
+                        (stats.average_decode_seconds / baseline_decode - 1.0) * 100.0
+                    );
+                }
+                if let Some(opaque_totals) = &opaque_totals {
+                    let size_cost = stats.total_size as i64 - opaque_totals[index] as i64;
+                    stats.alpha_preserved = Some(comparison.alpha_kept);
+                    stats.max_alpha_error = Some(comparison.max_alpha_error);
+                    stats.alpha_size_cost = Some(size_cost);
+                    println!(
+                        "{}: 透明度 {}（最大誤差 {}）、透明度によるサイズ増加 {:+} bytes",
+                        stats.format,
+                        if comparison.alpha_kept { "保持" } else { "破棄" },
+                        comparison.max_alpha_error,
+                        size_cost
+                    );
+                }
+                if let Some(sizes) = block_sizes.get(index) {
+                    for (block, &size) in options.metadata.iter().zip(sizes) {
+                        let preserved = (Some(size.is_some()), size);
+                        match block {
+                            MetadataBlock::Exif => (stats.exif_preserved, stats.exif_bytes) = preserved,
+                            MetadataBlock::Icc => (stats.icc_preserved, stats.icc_bytes) = preserved,
+                            MetadataBlock::Xmp => (stats.xmp_preserved, stats.xmp_bytes) = preserved,
+                        }
+                    }
+                    println!(
+                        "{}: メタデータ {}",
+                        stats.format,
+                        options.metadata.iter().zip(sizes).map(|(block, size)| match size {
+                            Some(size) => format!("{} 保持（{} bytes）", block.name(), size),
+                            None => format!("{} 破棄", block.name()),
+                        }).collect::<Vec<_>>().join("、")
+                    );
+                }
+                if let Some(stripped_totals) = &stripped_totals {
+                    let size_cost = stats.total_size as i64 - stripped_totals[index] as i64;
+                    stats.stripped_total_size = Some(stripped_totals[index]);
+                    stats.metadata_size_cost = Some(size_cost);
+                    println!("{}: -strip での合計サイズ {} bytes（メタデータによるサイズ増加 {:+} bytes）", stats.format, stripped_totals[index], size_cost);
+                }
+                if let Some(difference) = color_differences.get(index) {
+                    stats.profile_preserved = Some(difference.profile_kept);
+                    stats.mean_delta_e = Some(difference.mean_delta_e);
+                    stats.max_delta_e = Some(difference.max_delta_e);
+                    println!(
+                        "{}: ICCプロファイル {}、色差 ΔE2000 平均 {:.3} / 最大 {:.3}",
+                        stats.format,
+                        if difference.profile_kept { "保持" } else { "破棄" },
+                        difference.mean_delta_e,
+                        difference.max_delta_e
+                    );
+                }
+                // 可逆圧縮モードでは元画像のPNGに対するサイズ比も表示する
+                if options.lossless {
+                    println!("{}: PNG比 {:.1}%", stats.format, stats.total_size as f64 / source_total as f64 * 100.0);
+                }
+                // 8bitまでしか保存できないフォーマットは、16bitの画像では可逆にならないため確認しない
+                if format.is_lossless(variant.bit_depth) && max_error != 0 {
+                    not_lossless.push(format!("{} (最大誤差: {})", stats.format, max_error));
+                } else if format.lossless_bit_depth > 0 && !format.is_lossless(variant.bit_depth) {
+                    println!("{}: {}bitを保存できないため、可逆圧縮の確認の対象外です", stats.format, variant.bit_depth);
+                }
+                result_writer.serialize(&stats)?;
+            }
+            
+            // 画像ごとの結果を出力
+            if let Some(details_writer) = details_writer.as_mut() {
+                for ((format, seconds), comparison) in options.formats.iter().zip(&encode_seconds).zip(&comparisons) {
+                    for i in 0..image_count {
+                        details_writer.serialize(ImageDetail {
+                            run_id: manifest.run_id.clone(),
+                            run_number: run,
+                            image_index: i,
+                            seed: image_seed(seed, run, i),
+                            format: format.name.to_uppercase(),
+                            width: variant.width,
+                            height: variant.height,
+                            channels: variant.color_type.channels(),
+                            bit_depth: variant.bit_depth,
+                            alpha_pattern: variant.alpha().map(|pattern| pattern.name().to_string()),
+                            color_space: variant.color_space.name.to_string(),
+                            quality: format.quality(),
+                            size: fs::metadata(format.file_path(&output_dir, i))?.len(),
+                            encode_seconds: seconds.as_ref().map(|seconds| seconds[i as usize]),
+                            decode_seconds: comparison.decode_seconds[i as usize],
+                        })?;
+                    }
+                }
+            }
+            
+            // 可逆のはずのフォーマットが元画像と一致しない場合は、結果を保存し、調査できるよう画像を残して終了する
+            if !not_lossless.is_empty() {
+                result_writer.flush()?;
+                if let Some(details_writer) = details_writer.as_mut() {
+                    details_writer.flush()?;
+                }
+                return Err(format!(
+                    "可逆圧縮のはずのフォーマットで元画像と画素が一致しませんでした: {} (画像は{}に残しています)",
+                    not_lossless.join(", "),
+                    output_dir
+                ).into());
+            }
+            
+            // 次の組み合わせの画像と混ざらないよう、画像ファイルを削除
+            cleanup_images(&output_dir, image_count)?;
+        }
         
-        // PNG -> JPG/WebP変換（4並列）
-        convert_images_parallel(&output_dir, "png", "jpg", image_count).await?;
-        println!("JPG画像に変換しました");
+        fs::remove_dir(&output_dir)?;
+        println!("実行 {} 完了（画像ファイル削除済み）", run);
+    }
+    
+    result_writer.flush()?;
+    if let Some(details_writer) = details_writer.as_mut() {
+        details_writer.flush()?;
+    }
+    
+    // 実験終了後の最終クリーンアップ
+    cleanup_remaining_files(rounds)?;
+    
+    if interrupt::is_interrupted() {
+        println!("中断されました。完了した分の結果は{}に保存されました。", output_path.display());
+        std::process::exit(interrupt::EXIT_INTERRUPTED);
+    }
+    
+    println!("全ての実行が完了しました。結果は{}に保存されました。", output_path.display());
+    if options.details {
+        println!("画像ごとの結果は{}に保存されました。", details_path.display());
+    }
+    println!("実行環境は{}に保存されました。", manifest_path.display());
+    
+    Ok(())
+}
+
+/// 同じ画像群を1, 2, 4, …, `--jobs` ワーカーで変換し、ワーカー数ごとのスループットを記録する
+async fn run_scaling_benchmark(image_count: u32, rounds: u32, options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    let levels = pool::scaling_levels(options.jobs);
+    
+    println!("並列スケーリング測定（テスト版）開始: {}枚の画像で{}ラウンド実行", image_count, rounds);
+    println!("ワーカー数: {}", levels.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", "));
+    println!("フォーマット: {}", format_names(&options.formats));
+    println!("画像: {}", variant_labels(&options.variants));
+    
+    let seed = options.seed.unwrap_or_else(|| thread_rng().r#gen());
+    println!("シード: {}", seed);
+    
+    let output_path = options.output.clone()
+        .unwrap_or_else(|| output::default_path("image_format_comparison_scaling_test_results", options.output_format));
+    let mut result_writer = ResultWriter::create::<ScalingStats>(&output_path, options.output_format, options.append)?;
+    
+    // 実行環境を結果ファイルと同じ場所に記録
+    let manifest = RunManifest::collect(&output_path, Path::new("."), tools::resolved());
+    let manifest_path = manifest.write_next_to(&output_path, options.append)?;
+    println!("実行ID: {}", manifest.run_id);
+    
+    'rounds: for run in 1..=rounds {
+        if interrupt::is_interrupted() {
+            break;
+        }
         
-        convert_images_parallel(&output_dir, "png", "webp", image_count).await?;
-        println!("WebP画像に変換しました");
+        println!("実行回数: {}/{}（テスト）", run, rounds);
         
-        // 各形式の統計を計算
-        let png_stats = calculate_stats(&output_dir, "png", run, image_count)?;
-        let jpg_stats = calculate_stats(&output_dir, "jpg", run, image_count)?;
-        let webp_stats = calculate_stats(&output_dir, "webp", run, image_count)?;
+        let output_dir = format!("test_images_run_{}", run);
+        fs::create_dir_all(&output_dir)?;
         
-        // CSV出力
-        csv_writer.serialize(&png_stats)?;
-        csv_writer.serialize(&jpg_stats)?;
-        csv_writer.serialize(&webp_stats)?;
+        for variant in &options.variants {
+            // 全てのワーカー数で同じPNG画像を変換する
+            generate_random_png_images_parallel(&output_dir, variant, image_count, seed, run, options.jobs).await?;
+            println!("PNG画像{}枚を生成しました（{}）", image_count, variant.label());
+            
+            for format in options.formats.iter().filter(|format| !format.is_source()) {
+                let mut baseline = None;
+                
+                for &workers in &levels {
+                    let start = Instant::now();
+                    convert_images_parallel(&output_dir, format, image_count, workers).await?;
+                    let elapsed_seconds = start.elapsed().as_secs_f64();
+                    
+                    // 途中で中断された計測は記録しない
+                    if interrupt::is_interrupted() {
+                        break 'rounds;
+                    }
+                    
+                    let images_per_second = image_count as f64 / elapsed_seconds;
+                    let baseline = *baseline.get_or_insert(images_per_second);
+                    println!("{} {} {}ワーカー: {:.2}枚/秒 (1ワーカー比 {:.2}倍)", format.name.to_uppercase(), variant.label(), workers, images_per_second, images_per_second / baseline);
+                    
+                    result_writer.serialize(ScalingStats {
+                        run_id: manifest.run_id.clone(),
+                        run_number: run,
+                        format: format.name.to_uppercase(),
+                        width: variant.width,
+                        height: variant.height,
+                        channels: variant.color_type.channels(),
+                        bit_depth: variant.bit_depth,
+                        alpha_pattern: variant.alpha().map(|pattern| pattern.name().to_string()),
+                        color_space: variant.color_space.name.to_string(),
+                        workers,
+                        image_count,
+                        elapsed_seconds,
+                        images_per_second,
+                        speedup: images_per_second / baseline,
+                    })?;
+                    
+                    // 次のワーカー数でも同じ条件で変換するため、変換結果を消しておく
+                    remove_images(&output_dir, format, image_count)?;
+                }
+            }
+            
+            cleanup_images(&output_dir, image_count)?;
+        }
         
-        // 画像ファイルを削除
-        cleanup_images(&output_dir, image_count)?;
         fs::remove_dir(&output_dir)?;
         println!("実行 {} 完了（画像ファイル削除済み）", run);
     }
     
-    csv_writer.flush()?;
+    result_writer.flush()?;
     
     // 実験終了後の最終クリーンアップ
     cleanup_remaining_files(rounds)?;
-    println!("全ての実行が完了しました。結果はimage_format_comparison_test_results.csvに保存されました。");
+    
+    if interrupt::is_interrupted() {
+        println!("中断されました。完了した分の結果は{}に保存されました。", output_path.display());
+        std::process::exit(interrupt::EXIT_INTERRUPTED);
+    }
+    
+    println!("全ての実行が完了しました。結果は{}に保存されました。", output_path.display());
+    println!("実行環境は{}に保存されました。", manifest_path.display());
     
     Ok(())
 }
 
-async fn generate_random_png_images_parallel(output_dir: &str, count: u32) -> Result<(), Box<dyn std::error::Error>> {
-    let mut join_set = JoinSet::new();
-    let output_dir = Arc::new(output_dir.to_string());
+/// 元画像ごとに幅の異なる派生画像を各フォーマット・フィルタで作り、保存容量とCPU時間を記録する
+async fn run_derivative_benchmark(image_count: u32, rounds: u32, options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    println!("派生画像の測定（テスト版）開始: {}枚の画像で{}ラウンド実行", image_count, rounds);
+    println!("並列数: {}", options.jobs);
+    println!("派生画像の幅: {}", options.ladder.iter().map(|width| width.to_string()).collect::<Vec<_>>().join(", "));
+    println!("フィルタ: {}", options.filters.join(", "));
+    println!("フォーマット: {}", format_names(&options.formats));
+    println!("画像: {}", variant_labels(&options.variants));
+    
+    let seed = options.seed.unwrap_or_else(|| thread_rng().r#gen());
+    println!("シード: {}", seed);
     
-    // 4並列でタスクを分割
-    let chunk_size = count / 4;
-    for thread_id in 0..4 {
-        let start = thread_id * chunk_size;
-        let end = if thread_id == 3 { count } else { (thread_id + 1) * chunk_size };
-        let output_dir_clone = Arc::clone(&output_dir);
+    let output_path = options.output.clone()
+        .unwrap_or_else(|| output::default_path("image_format_comparison_derivative_test_results", options.output_format));
+    let mut result_writer = ResultWriter::create::<DerivativeStats>(&output_path, options.output_format, options.append)?;
+    
+    // 実行環境を結果ファイルと同じ場所に記録
+    let manifest = RunManifest::collect(&output_path, Path::new("."), tools::resolved());
+    let manifest_path = manifest.write_next_to(&output_path, options.append)?;
+    println!("実行ID: {}", manifest.run_id);
+    
+    'rounds: for run in 1..=rounds {
+        if interrupt::is_interrupted() {
+            break;
+        }
         
-        join_set.spawn(async move {
-            let mut rng = thread_rng();
-            for i in start..end {
-                // ランダムな色でノイズ画像を生成
-                let r: u8 = rng.r#gen();
-                let g: u8 = rng.r#gen();
-                let b: u8 = rng.r#gen();
-                
-                let output_path = format!("{}/image_{:03}.png", output_dir_clone.as_str(), i);
-                
-                let status = Command::new("convert")
-                    .args(&[
-                        "-size", "1024x1024",
-                        &format!("xc:rgb({},{},{})", r, g, b),
-                        "+noise", "Random",
-                        &output_path
-                    ])
-                    .status()
-                    .map_err(|e| format!("画像生成コマンド実行エラー: {}", e))?;
+        println!("実行回数: {}/{}", run, rounds);
+        
+        let output_dir = format!("test_images_run_{}", run);
+        fs::create_dir_all(&output_dir)?;
+        
+        for variant in &options.variants {
+            // 元画像より大きい派生画像は作らない（拡大せずに元画像を配信する想定）
+            let widths: Vec<u32> = options.ladder.iter().copied().filter(|&width| width <= variant.width).collect();
+            if widths.is_empty() {
+                println!("{}: 元画像より小さい派生画像の幅がないためスキップします", variant.label());
+                continue;
+            }
+            let ladder = widths.iter().map(|width| width.to_string()).collect::<Vec<_>>().join(",");
+            
+            generate_random_png_images_parallel(&output_dir, variant, image_count, seed, run, options.jobs).await?;
+            println!("PNG画像{}枚を生成しました（{}）", image_count, variant.label());
+            let original_total_size = calculate_stats(&output_dir, &manifest.run_id, formats::source(), variant, run, image_count)?.total_size;
+            
+            for format in &options.formats {
+                for &filter in &options.filters {
+                    let cpu_start = derivative::children_cpu_seconds();
+                    let start = Instant::now();
+                    let derivative_total_size = derivative::ladder_total_size(&output_dir, format, filter, &widths, image_count, options.jobs).await?;
+                    let elapsed_seconds = start.elapsed().as_secs_f64();
+                    let cpu_seconds = derivative::children_cpu_seconds() - cpu_start;
                     
-                if !status.success() {
-                    return Err(format!("画像生成に失敗しました: {}", output_path));
+                    // 途中で中断された計測は記録しない
+                    if interrupt::is_interrupted() {
+                        break 'rounds;
+                    }
+                    
+                    let storage_per_original = (original_total_size + derivative_total_size) as f64 / image_count as f64;
+                    println!(
+                        "{} {} {}: 元画像1枚あたり {:.0} bytes（派生画像 {} bytes）、CPU時間 {:.3} 秒",
+                        format.name.to_uppercase(),
+                        variant.label(),
+                        filter,
+                        storage_per_original,
+                        derivative_total_size / image_count as u64,
+                        cpu_seconds
+                    );
+                    
+                    result_writer.serialize(DerivativeStats {
+                        run_id: manifest.run_id.clone(),
+                        run_number: run,
+                        format: format.name.to_uppercase(),
+                        width: variant.width,
+                        height: variant.height,
+                        channels: variant.color_type.channels(),
+                        bit_depth: variant.bit_depth,
+                        alpha_pattern: variant.alpha().map(|pattern| pattern.name().to_string()),
+                        color_space: variant.color_space.name.to_string(),
+                        filter: filter.to_string(),
+                        ladder: ladder.clone(),
+                        image_count,
+                        derivative_count: image_count * widths.len() as u32,
+                        original_total_size,
+                        derivative_total_size,
+                        storage_per_original,
+                        cpu_seconds,
+                        cpu_seconds_per_original: cpu_seconds / image_count as f64,
+                        elapsed_seconds,
+                    })?;
                 }
             }
-            Ok::<(), String>(())
-        });
+            
+            cleanup_images(&output_dir, image_count)?;
+        }
+        
+        fs::remove_dir(&output_dir)?;
+        println!("実行 {} 完了（画像ファイル削除済み）", run);
     }
     
-    // 全てのタスクの完了を待つ
-    while let Some(result) = join_set.join_next().await {
-        match result {
-            Ok(Ok(())) => {},
-            Ok(Err(e)) => return Err(e.into()),
-            Err(e) => return Err(format!("並列実行エラー: {}", e).into()),
-        }
+    result_writer.flush()?;
+    
+    // 実験終了後の最終クリーンアップ
+    cleanup_remaining_files(rounds)?;
+    
+    if interrupt::is_interrupted() {
+        println!("中断されました。完了した分の結果は{}に保存されました。", output_path.display());
+        std::process::exit(interrupt::EXIT_INTERRUPTED);
     }
     
+    println!("全ての実行が完了しました。結果は{}に保存されました。", output_path.display());
+    println!("実行環境は{}に保存されました。", manifest_path.display());
+    
     Ok(())
 }
 
-async fn convert_images_parallel(output_dir: &str, from_format: &str, to_format: &str, count: u32) -> Result<(), Box<dyn std::error::Error>> {
-    let mut join_set = JoinSet::new();
+fn format_names(formats: &[&ImageFormat]) -> String {
+    formats.iter().map(|format| format.name).collect::<Vec<_>>().join(", ")
+}
+
+fn average(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        0.0
+    } else {
+        values.iter().sum::<f64>() / values.len() as f64
+    }
+}
+
+fn metadata_names(blocks: &[MetadataBlock]) -> String {
+    blocks.iter().map(|block| block.name()).collect::<Vec<_>>().join(", ")
+}
+
+fn variant_labels(variants: &[ImageVariant]) -> String {
+    variants.iter().map(ImageVariant::label).collect::<Vec<_>>().join(", ")
+}
+
+/// 画像ごとのシード。ラウンドごとに異なる画像になるよう、ラウンド番号を上位32bitに加える
+fn image_seed(seed: u64, run: u32, index: u32) -> u64 {
+    seed.wrapping_add((run as u64) << 32).wrapping_add(index as u64)
+}
+
+async fn generate_random_png_images_parallel(
+    output_dir: &str,
+    variant: &ImageVariant,
+    count: u32,
+    seed: u64,
+    run: u32,
+    jobs: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
     let output_dir = Arc::new(output_dir.to_string());
-    let from_format = Arc::new(from_format.to_string());
-    let to_format = Arc::new(to_format.to_string());
-    
-    // 4並列でタスクを分割
-    let chunk_size = count / 4;
-    for thread_id in 0..4 {
-        let start = thread_id * chunk_size;
-        let end = if thread_id == 3 { count } else { (thread_id + 1) * chunk_size };
-        let output_dir_clone = Arc::clone(&output_dir);
-        let from_format_clone = Arc::clone(&from_format);
-        let to_format_clone = Arc::clone(&to_format);
+    let variant = *variant;
+    
+    // 1枚ずつ空いたワーカーに割り当てる
+    pool::for_each_image(count, jobs, move |i| {
+        let output_dir = Arc::clone(&output_dir);
         
-        join_set.spawn(async move {
-            for i in start..end {
-                let input_path = format!("{}/image_{:03}.{}", output_dir_clone.as_str(), i, from_format_clone.as_str());
-                let output_path = format!("{}/image_{:03}.{}", output_dir_clone.as_str(), i, to_format_clone.as_str());
+        async move {
+            // ランダムな色でノイズ画像を生成
+            let output_path = formats::source().file_path(&output_dir, i);
+            
+            let status = variant.generate_command(image_seed(seed, run, i), &output_path)
+                .status()
+                .await
+                .map_err(|e| format!("画像生成コマンド実行エラー: {}", e))?;
                 
-                let mut args = vec![input_path.as_str()];
-                
-                // 劣化なしの設定
-                match to_format_clone.as_str() {
-                    "jpg" => {
-                        args.extend_from_slice(&["-quality", "100"]);
-                    },
-                    "webp" => {
-                        args.extend_from_slice(&["-quality", "100"]);
-                    },
-                    _ => {}
-                }
-                
-                args.push(output_path.as_str());
+            if !status.success() {
+                return Err(format!("画像生成に失敗しました: {}", output_path));
+            }
+            Ok(())
+        }
+    })
+    .await
+}
+
+/// 元画像に `convert <args>` を適用した画像を `<出力先>/<name>` に作って各フォーマットに変換し、
+/// フォーマットごとの合計サイズを返す
+///
+/// 透明度を除いた画像（`-alpha off`）やメタデータを除いた画像（`-strip`）と比べ、
+/// 元画像の合計サイズとの差からそれらを保持するためにかかるサイズを求めるために使う。
+async fn derived_total_sizes(
+    output_dir: &str,
+    name: &str,
+    args: &'static [&'static str],
+    formats: &[&'static ImageFormat],
+    count: u32,
+    jobs: usize,
+) -> Result<Vec<u64>, Box<dyn std::error::Error>> {
+    let derived_dir = format!("{}/{}", output_dir, name);
+    fs::create_dir_all(&derived_dir)?;
+    
+    let source_dir = Arc::new(output_dir.to_string());
+    let target_dir = Arc::new(derived_dir.clone());
+    pool::for_each_image(count, jobs, move |i| {
+        let source_dir = Arc::clone(&source_dir);
+        let target_dir = Arc::clone(&target_dir);
+        
+        async move {
+            let input_path = formats::source().file_path(&source_dir, i);
+            let output_path = formats::source().file_path(&target_dir, i);
+            
+            let status = imagemagick::command()
+                .arg(&input_path)
+                .args(args)
+                .args(["-define", "png:exclude-chunk=date,time", &output_path])
+                .status()
+                .await
+                .map_err(|e| format!("比較用画像の作成コマンド実行エラー: {}", e))?;
                 
-                let status = Command::new("convert")
-                    .args(&args)
-                    .status()
-                    .map_err(|e| format!("変換コマンド実行エラー: {}", e))?;
-                    
-                if !status.success() {
-                    return Err(format!("画像変換に失敗しました: {} -> {}", input_path, output_path));
-                }
+            if !status.success() {
+                return Err(format!("比較用画像の作成に失敗しました: {}", output_path));
             }
-            Ok::<(), String>(())
-        });
-    }
+            Ok(())
+        }
+    })
+    .await?;
     
-    // 全てのタスクの完了を待つ
-    while let Some(result) = join_set.join_next().await {
-        match result {
-            Ok(Ok(())) => {},
-            Ok(Err(e)) => return Err(e.into()),
-            Err(e) => return Err(format!("並列実行エラー: {}", e).into()),
+    let mut totals = Vec::new();
+    for format in formats {
+        if interrupt::is_interrupted() {
+            break;
         }
+        if !format.is_source() {
+            convert_images_parallel(&derived_dir, format, count, jobs).await?;
+        }
+        let mut total = 0;
+        for i in 0..count {
+            total += fs::metadata(format.file_path(&derived_dir, i))?.len();
+        }
+        totals.push(total);
     }
     
-    Ok(())
+    cleanup_images(&derived_dir, count)?;
+    fs::remove_dir(&derived_dir)?;
+    Ok(totals)
+}
+
+/// 全画像を変換し、画像ごとの変換時間（秒）を画像番号順に返す
+async fn convert_images_parallel(output_dir: &str, format: &'static ImageFormat, count: u32, jobs: usize) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let output_dir = Arc::new(output_dir.to_string());
+    let encode_seconds = Arc::new(Mutex::new(vec![0.0; count as usize]));
+    let encode_seconds_clone = Arc::clone(&encode_seconds);
+    
+    // 1枚ずつ空いたワーカーに割り当てる
+    pool::for_each_image(count, jobs, move |i| {
+        let output_dir = Arc::clone(&output_dir);
+        let encode_seconds = Arc::clone(&encode_seconds_clone);
+        
+        async move {
+            let input_path = formats::source().file_path(&output_dir, i);
+            let output_path = format.file_path(&output_dir, i);
+            
+            let start = Instant::now();
+            let status = format.encode_command(&input_path, &output_path)
+                .status()
+                .await
+                .map_err(|e| format!("変換コマンド実行エラー: {}", e))?;
+                
+            if !status.success() {
+                return Err(format!("画像変換に失敗しました: {} -> {}", input_path, output_path));
+            }
+            encode_seconds.lock().unwrap()[i as usize] = start.elapsed().as_secs_f64();
+            Ok(())
+        }
+    })
+    .await?;
+    
+    let encode_seconds = std::mem::take(&mut *encode_seconds.lock().unwrap());
+    Ok(encode_seconds)
 }
 
-fn calculate_stats(output_dir: &str, format: &str, run_number: u32, image_count: u32) -> Result<ImageStats, Box<dyn std::error::Error>> {
+fn calculate_stats(output_dir: &str, run_id: &str, format: &ImageFormat, variant: &ImageVariant, run_number: u32, image_count: u32) -> Result<ImageStats, Box<dyn std::error::Error>> {
     let mut sizes: Vec<u64> = Vec::new();
     
     for i in 0..image_count {
-        let file_path = format!("{}/image_{:03}.{}", output_dir, i, format);
+        let file_path = format.file_path(output_dir, i);
         let metadata = fs::metadata(&file_path)?;
         sizes.push(metadata.len());
     }
@@ -249,7 +876,7 @@ fn calculate_stats(output_dir: &str, format: &str, run_number: u32, image_count:
     let max_size = *sizes.last().unwrap();
     
     // 中央値計算
-    let median_size = if sizes.len() % 2 == 0 {
+    let median_size = if sizes.len().is_multiple_of(2) {
         let mid = sizes.len() / 2;
         (sizes[mid - 1] + sizes[mid]) as f64 / 2.0
     } else {
@@ -257,25 +884,56 @@ fn calculate_stats(output_dir: &str, format: &str, run_number: u32, image_count:
     };
     
     Ok(ImageStats {
+        run_id: run_id.to_string(),
         run_number,
-        format: format.to_uppercase(),
+        format: format.name.to_uppercase(),
+        width: variant.width,
+        height: variant.height,
+        channels: variant.color_type.channels(),
+        bit_depth: variant.bit_depth,
+        alpha_pattern: variant.alpha().map(|pattern| pattern.name().to_string()),
+        color_space: variant.color_space.name.to_string(),
         total_size,
         average_size,
         min_size,
         max_size,
         median_size,
+        // デコード時間と画素の比較結果は呼び出し側で設定する
+        average_decode_seconds: 0.0,
+        lossless_verified: false,
+        max_channel_error: 0,
+        psnr: None,
+        alpha_preserved: None,
+        max_alpha_error: None,
+        alpha_size_cost: None,
+        exif_preserved: None,
+        exif_bytes: None,
+        icc_preserved: None,
+        icc_bytes: None,
+        xmp_preserved: None,
+        xmp_bytes: None,
+        stripped_total_size: None,
+        metadata_size_cost: None,
+        profile_preserved: None,
+        mean_delta_e: None,
+        max_delta_e: None,
     })
 }
 
 fn cleanup_images(output_dir: &str, image_count: u32) -> Result<(), Box<dyn std::error::Error>> {
-    let formats = ["png", "jpg", "webp"];
+    // 今回比較しなかったフォーマットも、以前の実行の残りがあれば削除する
+    for format in formats::FORMATS {
+        remove_images(output_dir, format, image_count)?;
+    }
     
-    for format in &formats {
-        for i in 0..image_count {
-            let file_path = format!("{}/image_{:03}.{}", output_dir, i, format);
-            if fs::metadata(&file_path).is_ok() {
-                fs::remove_file(&file_path)?;
-            }
+    Ok(())
+}
+
+fn remove_images(output_dir: &str, format: &ImageFormat, image_count: u32) -> Result<(), Box<dyn std::error::Error>> {
+    for i in 0..image_count {
+        let file_path = format.file_path(output_dir, i);
+        if fs::metadata(&file_path).is_ok() {
+            fs::remove_file(&file_path)?;
         }
     }
     
@@ -291,7 +949,12 @@ fn cleanup_remaining_files(rounds: u32) -> Result<(), Box<dyn std::error::Error>
             let entries = fs::read_dir(&dir_name)?;
             for entry in entries {
                 let entry = entry?;
-                fs::remove_file(entry.path())?;
+                // 透明度やメタデータを除いた画像の作業ディレクトリ（opaque・stripped）はディレクトリごと削除
+                if entry.file_type()?.is_dir() {
+                    fs::remove_dir_all(entry.path())?;
+                } else {
+                    fs::remove_file(entry.path())?;
+                }
             }
             // 空のディレクトリを削除
             fs::remove_dir(&dir_name)?;
@@ -0,0 +1,308 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::process::Command;
+use benchmark_common::imagemagick;
+
+use crate::icc::{self, RgbColorSpace};
+
+/// 生成する画像の色の種類
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorType {
+    Gray,
+    Rgb,
+    Rgba,
+}
+
+impl ColorType {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "gray" | "grey" => Ok(ColorType::Gray),
+            "rgb" => Ok(ColorType::Rgb),
+            "rgba" => Ok(ColorType::Rgba),
+            _ => Err(format!("未対応の色の種類です: {} (対応: gray, rgb, rgba)", value)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorType::Gray => "gray",
+            ColorType::Rgb => "rgb",
+            ColorType::Rgba => "rgba",
+        }
+    }
+
+    pub fn channels(self) -> u8 {
+        match self {
+            ColorType::Gray => 1,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+}
+
+/// RGBA画像の透明度の付け方
+#[derive(Clone, Copy, PartialEq)]
+pub enum AlphaPattern {
+    /// 画素ごとにランダムな透明度
+    Noise,
+    /// 上端の不透明から下端の完全な透明まで滑らかに変化
+    Gradient,
+    /// 中央の円の内側だけ不透明で、外側は完全な透明（切り抜き画像）
+    Cutout,
+    /// 透明度のチャンネルはあるが全画素が不透明
+    Opaque,
+}
+
+pub static ALPHA_PATTERNS: &[AlphaPattern] = &[AlphaPattern::Noise, AlphaPattern::Gradient, AlphaPattern::Cutout, AlphaPattern::Opaque];
+
+impl AlphaPattern {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim().to_ascii_lowercase();
+        ALPHA_PATTERNS.iter().copied().find(|pattern| pattern.name() == value).ok_or_else(|| {
+            format!(
+                "未対応の透明度パターンです: {} (対応: {})",
+                value,
+                ALPHA_PATTERNS.iter().map(|pattern| pattern.name()).collect::<Vec<_>>().join(", ")
+            )
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AlphaPattern::Noise => "noise",
+            AlphaPattern::Gradient => "gradient",
+            AlphaPattern::Cutout => "cutout",
+            AlphaPattern::Opaque => "opaque",
+        }
+    }
+
+    /// 透明度として使うグレースケール画像（白が不透明）を作る `convert` の引数
+    fn mask_args(self, width: u32, height: u32) -> Vec<String> {
+        let size = format!("{}x{}", width, height);
+        let args: Vec<String> = match self {
+            AlphaPattern::Noise => vec![size, "xc:gray50".into(), "+noise".into(), "Random".into(), "-colorspace".into(), "Gray".into()],
+            AlphaPattern::Gradient => vec![size, "gradient:white-black".into()],
+            AlphaPattern::Cutout => {
+                let (cx, cy) = (width / 2, height / 2);
+                let radius = width.min(height) / 2;
+                vec![size, "xc:black".into(), "-fill".into(), "white".into(), "-draw".into(), format!("circle {},{} {},{}", cx, cy, cx, cy.saturating_sub(radius))]
+            },
+            AlphaPattern::Opaque => vec![size, "xc:white".into()],
+        };
+        std::iter::once("-size".to_string()).chain(args).collect()
+    }
+}
+
+/// 生成する画像の寸法・色の種類・ビット深度・色空間の組
+#[derive(Clone, Copy, PartialEq)]
+pub struct ImageVariant {
+    pub width: u32,
+    pub height: u32,
+    pub color_type: ColorType,
+    pub bit_depth: u8,
+    /// RGBAの場合の透明度の付け方（RGBAでなければ使わない）
+    pub alpha_pattern: AlphaPattern,
+    /// 画素値の色空間（sRGB以外では生成後にその色空間のICCプロファイルを埋め込む）
+    pub color_space: &'static RgbColorSpace,
+}
+
+/// これまでの固定の生成条件（1024x1024、8bit RGB）
+pub const DEFAULT: ImageVariant = ImageVariant {
+    width: 1024,
+    height: 1024,
+    color_type: ColorType::Rgb,
+    bit_depth: 8,
+    alpha_pattern: AlphaPattern::Noise,
+    color_space: &icc::SRGB,
+};
+
+/// `--sizes` で寸法の代わりに指定できる名前
+const SIZE_PRESETS: &[(&str, &[(u32, u32)])] = &[
+    ("thumbnails", &[(128, 128), (256, 256), (512, 512)]),
+    ("photo", &[(4000, 3000)]),
+];
+
+impl ImageVariant {
+    /// `1024x1024 rgb 8bit` や `1024x1024 rgba(cutout) 8bit display-p3` のような表示用の名前
+    pub fn label(&self) -> String {
+        let label = match self.alpha() {
+            Some(pattern) => format!("{}x{} {}({}) {}bit", self.width, self.height, self.color_type.name(), pattern.name(), self.bit_depth),
+            None => format!("{}x{} {} {}bit", self.width, self.height, self.color_type.name(), self.bit_depth),
+        };
+        if self.is_wide_gamut() {
+            format!("{} {}", label, self.color_space.name)
+        } else {
+            label
+        }
+    }
+
+    /// sRGB以外の色空間の画像か
+    pub fn is_wide_gamut(&self) -> bool {
+        *self.color_space != icc::SRGB
+    }
+
+    /// 透明度のある画像の場合は、その透明度の付け方
+    pub fn alpha(&self) -> Option<AlphaPattern> {
+        (self.color_type == ColorType::Rgba).then_some(self.alpha_pattern)
+    }
+
+    /// 画像ごとのシード `seed` からノイズ画像を生成し、PNGとして `output` に保存するコマンド
+    ///
+    /// 同じシードであれば、並列数や生成順に関係なくバイト単位で同じ画像になる。
+    pub fn generate_command(&self, seed: u64, output: &str) -> Command {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let r: u8 = rng.r#gen();
+        let g: u8 = rng.r#gen();
+        let b: u8 = rng.r#gen();
+        let noise_seed: u32 = rng.r#gen();
+        let size = format!("{}x{}", self.width, self.height);
+
+        let mut command = imagemagick::command();
+        command.args([
+            "-seed", &noise_seed.to_string(),
+            "-size", &size,
+            &format!("xc:rgb({},{},{})", r, g, b),
+            "+noise", "Random",
+        ]);
+        match self.color_type {
+            ColorType::Gray => {
+                command.args(["-colorspace", "Gray", "-type", "Grayscale"]);
+            },
+            ColorType::Rgb => {},
+            // 透明度のパターンのグレースケール画像を透明度として重ねる
+            ColorType::Rgba => {
+                command.arg("(").args(self.alpha_pattern.mask_args(self.width, self.height)).arg(")");
+                command.args(["-alpha", "off", "-compose", "CopyOpacity", "-composite", "-type", "TrueColorAlpha"]);
+            },
+        }
+        if self.bit_depth != 8 {
+            command.args(["-depth", &self.bit_depth.to_string()]);
+        }
+        // 作成日時のチャンクを含めず、同じシードならバイト単位で同じ画像にする
+        command.args(["-define", "png:exclude-chunk=date,time"]).arg(output);
+        command
+    }
+}
+
+/// `128x128,4000x3000` や `thumbnails` のような寸法の一覧を解釈する
+pub fn parse_sizes(value: &str) -> Result<Vec<(u32, u32)>, String> {
+    let invalid = || format!("寸法の指定が不正です: {} (例: 1024x1024,4000x3000 / {})", value, preset_names());
+
+    let mut sizes = Vec::new();
+    for item in value.split(',').map(|item| item.trim().to_ascii_lowercase()).filter(|item| !item.is_empty()) {
+        let items = match SIZE_PRESETS.iter().find(|(name, _)| *name == item) {
+            Some((_, sizes)) => sizes.to_vec(),
+            None => vec![parse_size(&item).ok_or_else(invalid)?],
+        };
+        for size in items {
+            if !sizes.contains(&size) {
+                sizes.push(size);
+            }
+        }
+    }
+
+    if sizes.is_empty() {
+        return Err(invalid());
+    }
+    Ok(sizes)
+}
+
+/// `1024x1024` のような1つの寸法を解釈する
+pub fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.trim().split_once(['x', 'X'])?;
+    let width: u32 = width.parse().ok().filter(|&width| width > 0)?;
+    let height: u32 = height.parse().ok().filter(|&height| height > 0)?;
+    Some((width, height))
+}
+
+/// `gray,rgb,rgba` のような色の種類の一覧を解釈する
+pub fn parse_color_types(value: &str) -> Result<Vec<ColorType>, String> {
+    let mut color_types = Vec::new();
+    for item in value.split(',').filter(|item| !item.trim().is_empty()) {
+        let color_type = ColorType::parse(item)?;
+        if !color_types.contains(&color_type) {
+            color_types.push(color_type);
+        }
+    }
+
+    if color_types.is_empty() {
+        return Err("--color-types には1つ以上の色の種類を指定してください".to_string());
+    }
+    Ok(color_types)
+}
+
+pub fn parse_bit_depth(value: &str) -> Result<u8, String> {
+    match value.trim() {
+        "8" => Ok(8),
+        "16" => Ok(16),
+        _ => Err(format!("ビット深度は8または16で指定してください: {}", value)),
+    }
+}
+
+/// `8,16` のようなビット深度の一覧を解釈する
+pub fn parse_bit_depths(value: &str) -> Result<Vec<u8>, String> {
+    let mut bit_depths = Vec::new();
+    for item in value.split(',').filter(|item| !item.trim().is_empty()) {
+        let bit_depth = parse_bit_depth(item)?;
+        if !bit_depths.contains(&bit_depth) {
+            bit_depths.push(bit_depth);
+        }
+    }
+
+    if bit_depths.is_empty() {
+        return Err("--bit-depths には1つ以上のビット深度を指定してください".to_string());
+    }
+    Ok(bit_depths)
+}
+
+/// `noise,cutout` のような透明度パターンの一覧を解釈する（`all` で全て）
+pub fn parse_alpha_patterns(value: &str) -> Result<Vec<AlphaPattern>, String> {
+    if value.trim().eq_ignore_ascii_case("all") {
+        return Ok(ALPHA_PATTERNS.to_vec());
+    }
+
+    let mut patterns = Vec::new();
+    for item in value.split(',').filter(|item| !item.trim().is_empty()) {
+        let pattern = AlphaPattern::parse(item)?;
+        if !patterns.contains(&pattern) {
+            patterns.push(pattern);
+        }
+    }
+
+    if patterns.is_empty() {
+        return Err("--alpha-patterns には1つ以上のパターンを指定してください".to_string());
+    }
+    Ok(patterns)
+}
+
+/// 寸法・色の種類・ビット深度・色空間の全ての組み合わせ（寸法ごとにまとめた順）
+///
+/// RGBAは透明度パターン `alpha_patterns` ごとに別の組み合わせとする。
+/// グレースケールにはRGBのICCプロファイルを埋め込めないため、色空間は常にsRGBとする。
+pub fn matrix(
+    sizes: &[(u32, u32)],
+    color_types: &[ColorType],
+    bit_depths: &[u8],
+    alpha_patterns: &[AlphaPattern],
+    color_spaces: &[&'static RgbColorSpace],
+) -> Vec<ImageVariant> {
+    let mut variants = Vec::new();
+    for &(width, height) in sizes {
+        for &color_type in color_types {
+            let patterns = if color_type == ColorType::Rgba { alpha_patterns } else { &[DEFAULT.alpha_pattern][..] };
+            let spaces = if color_type == ColorType::Gray { &[DEFAULT.color_space][..] } else { color_spaces };
+            for &alpha_pattern in patterns {
+                for &color_space in spaces {
+                    for &bit_depth in bit_depths {
+                        variants.push(ImageVariant { width, height, color_type, bit_depth, alpha_pattern, color_space });
+                    }
+                }
+            }
+        }
+    }
+    variants
+}
+
+fn preset_names() -> String {
+    SIZE_PRESETS.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ")
+}
@@ -0,0 +1,150 @@
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use benchmark_common::imagemagick;
+use benchmark_common::pool;
+
+use crate::formats::{self, ImageFormat};
+
+/// 画像をImageMagickでデコードし、`bit_depth`（8または16）ビットのRGBAのチャンネル値の列として返す
+///
+/// どのフォーマットも同じデコーダーを通すことで、ファイル形式の違いに関係なく画素だけを比較できる。
+/// グレースケールや透明度のない画像も、RGBAの4チャンネルに展開される。
+pub async fn decode_rgba(path: &str, bit_depth: u8) -> Result<Vec<u16>, String> {
+    let output = imagemagick::command()
+        .args([path, "-depth", &bit_depth.to_string(), "-endian", "MSB", "rgba:-"])
+        .output()
+        .await
+        .map_err(|e| format!("デコードコマンド実行エラー: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("画像のデコードに失敗しました: {}", path));
+    }
+    Ok(if bit_depth > 8 {
+        output.stdout.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect()
+    } else {
+        output.stdout.iter().map(|&value| value as u16).collect()
+    })
+}
+
+/// 画像が透明度のチャンネルを持っているか（ImageMagickの `%A` が `True` または `Blend`）
+pub async fn has_alpha(path: &str) -> Result<bool, String> {
+    let output = imagemagick::command()
+        .args([path, "-format", "%A", "info:"])
+        .output()
+        .await
+        .map_err(|e| format!("画像情報コマンド実行エラー: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("画像情報の取得に失敗しました: {}", path));
+    }
+    let value = String::from_utf8_lossy(&output.stdout);
+    Ok(matches!(value.trim(), "True" | "Blend"))
+}
+
+/// 変換後の画像と元画像の画素の比較結果
+pub struct PixelComparison {
+    /// 全チャンネルでの最大絶対誤差（元画像のビット深度での値、0 であれば完全に透明な画素のRGBを除いて全画像が一致）
+    pub max_channel_error: u16,
+    /// 透明度のチャンネルのみの最大絶対誤差
+    pub max_alpha_error: u16,
+    /// 全画像が透明度のチャンネルを保持していたか（`check_alpha` を指定した場合のみ調べる）
+    pub alpha_kept: bool,
+    /// 全画像のRGBチャンネルでのPSNR（dB、全画像が一致した場合は `None`）
+    pub psnr: Option<f64>,
+    /// 画像ごとの、変換後の画像のデコードにかかった時間（秒）
+    pub decode_seconds: Vec<f64>,
+}
+
+/// 全画像について、変換後の画像を元画像と画素単位で比較する
+///
+/// 誤差は元画像のビット深度 `bit_depth` での値。透明度のない画像もRGBAに展開して比較するため、
+/// 透明度の誤差は不透明な元画像に対して変換後の画像が透明度を持った場合にのみ生じる。
+/// 元画像で完全に透明な画素は見えないため、エンコーダーがRGBを書き換えても誤差に含めず、透明度のみ比較する。
+/// デコード時間は `convert` の起動を含むため、フォーマット間の相対的な比較に使う。
+pub async fn compare_pixels(
+    output_dir: &str,
+    format: &'static ImageFormat,
+    bit_depth: u8,
+    check_alpha: bool,
+    count: u32,
+    jobs: usize,
+) -> Result<PixelComparison, Box<dyn std::error::Error>> {
+    let output_dir = Arc::new(output_dir.to_string());
+    let max_error = Arc::new(AtomicU16::new(0));
+    let max_alpha_error = Arc::new(AtomicU16::new(0));
+    let alpha_kept = Arc::new(AtomicBool::new(true));
+    let (max_error_clone, max_alpha_error_clone, alpha_kept_clone) = (Arc::clone(&max_error), Arc::clone(&max_alpha_error), Arc::clone(&alpha_kept));
+    let decode_seconds = Arc::new(Mutex::new(vec![0.0; count as usize]));
+    let decode_seconds_clone = Arc::clone(&decode_seconds);
+    // PSNRを求めるための、RGBチャンネルの誤差の二乗和とチャンネル値の数（完全に透明な画素を除く）
+    let squared_errors = Arc::new(Mutex::new((0.0, 0u64)));
+    let squared_errors_clone = Arc::clone(&squared_errors);
+    let max_value = (u16::MAX >> (16 - bit_depth)) as f64;
+
+    pool::for_each_image(count, jobs, move |i| {
+        let output_dir = Arc::clone(&output_dir);
+        let max_error = Arc::clone(&max_error_clone);
+        let max_alpha_error = Arc::clone(&max_alpha_error_clone);
+        let alpha_kept = Arc::clone(&alpha_kept_clone);
+        let decode_seconds = Arc::clone(&decode_seconds_clone);
+        let squared_errors = Arc::clone(&squared_errors_clone);
+
+        async move {
+            let source_path = formats::source().file_path(&output_dir, i);
+            let output_path = format.file_path(&output_dir, i);
+
+            let source = decode_rgba(&source_path, bit_depth).await?;
+            let start = Instant::now();
+            let decoded = decode_rgba(&output_path, bit_depth).await?;
+            decode_seconds.lock().unwrap()[i as usize] = start.elapsed().as_secs_f64();
+            // 画像サイズが異なる場合は画素を対応付けられないため、最大の誤差とみなす
+            let (error, alpha_error, squared_error, values) = if source.len() != decoded.len() {
+                let max = u16::MAX >> (16 - bit_depth);
+                let values = (source.len() / 4 * 3) as u64;
+                (max, max, max_value * max_value * values as f64, values)
+            } else {
+                let (mut error, mut alpha_error, mut squared_error, mut values) = (0, 0, 0.0, 0);
+                for (a, b) in source.chunks_exact(4).zip(decoded.chunks_exact(4)) {
+                    alpha_error = alpha_error.max(a[3].abs_diff(b[3]));
+                    error = error.max(alpha_error);
+                    if a[3] == 0 {
+                        continue;
+                    }
+                    for channel in 0..3 {
+                        let difference = a[channel].abs_diff(b[channel]);
+                        error = error.max(difference);
+                        squared_error += (difference as f64).powi(2);
+                    }
+                    values += 3;
+                }
+                (error, alpha_error, squared_error, values)
+            };
+            {
+                let mut squared_errors = squared_errors.lock().unwrap();
+                squared_errors.0 += squared_error;
+                squared_errors.1 += values;
+            }
+            max_error.fetch_max(error, Ordering::Relaxed);
+            max_alpha_error.fetch_max(alpha_error, Ordering::Relaxed);
+
+            if check_alpha && !has_alpha(&output_path).await? {
+                alpha_kept.store(false, Ordering::Relaxed);
+            }
+            Ok(())
+        }
+    })
+    .await?;
+
+    let (squared_error, values) = *squared_errors.lock().unwrap();
+    let psnr = (squared_error > 0.0).then(|| 10.0 * (max_value * max_value / (squared_error / values as f64)).log10());
+
+    Ok(PixelComparison {
+        max_channel_error: max_error.load(Ordering::Relaxed),
+        max_alpha_error: max_alpha_error.load(Ordering::Relaxed),
+        alpha_kept: alpha_kept.load(Ordering::Relaxed),
+        psnr,
+        decode_seconds: std::mem::take(&mut *decode_seconds.lock().unwrap()),
+    })
+}
@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+
+use benchmark_common::output::OutputFormat;
+use benchmark_common::pool;
+use benchmark_common::tools::{self, Tool};
+
+use crate::formats::{self, QualityFormat};
+use crate::variant::{self, ImageVariant};
+use crate::target::QualityTarget;
+
+/// このベンチマークで使う外部コマンド（`--tool` で指定できる名前）
+pub static TOOLS: &[Tool] = &[
+    // 品質の効き方はデリゲートのライブラリによって変わるため、デリゲートの一覧も記録する
+    Tool { name: "imagemagick", candidates: &["magick", "convert"], version_args: &["-version"], version_keywords: &["Version:", "Delegates"] },
+];
+
+/// 位置引数以外のコマンドラインオプション
+pub struct Options {
+    pub output_format: OutputFormat,
+    pub output: Option<PathBuf>,
+    pub append: bool,
+    pub jobs: usize,
+    pub seed: Option<u64>,
+    pub details: bool,
+    /// `--sizes` / `--color-types` / `--bit-depths` の全ての組み合わせ
+    pub variants: Vec<ImageVariant>,
+    pub formats: Vec<&'static QualityFormat>,
+    /// 指定がない場合は各プログラムの既定の品質を使う
+    pub qualities: Option<Vec<u32>>,
+    pub target: Option<QualityTarget>,
+    /// 品質ごとに知覚的な画質（SSIMULACRA2）を計算する
+    pub ssimulacra2: bool,
+    /// 品質ごとに残す変換後の画像の枚数（0なら残さない）
+    pub keep_samples: u32,
+    /// 品質の異なる出力がバイト単位で同じだった場合に、警告ではなくエラーで終了する
+    pub strict_quality_check: bool,
+    /// `--tool <名前>=<パス>` で指定した外部コマンドのパス
+    pub tools: Vec<(String, PathBuf)>,
+}
+
+/// `--name value` / `--name=value` 形式のオプションを取り出し、残りの位置引数と合わせて返す
+pub fn split_options(args: &[String]) -> Result<(Options, Vec<String>), Box<dyn std::error::Error>> {
+    let mut options = Options {
+        output_format: OutputFormat::Csv,
+        output: None,
+        append: false,
+        jobs: pool::default_jobs(),
+        seed: None,
+        details: false,
+        variants: vec![variant::DEFAULT],
+        formats: formats::default_formats(),
+        qualities: None,
+        target: None,
+        ssimulacra2: false,
+        keep_samples: 0,
+        strict_quality_check: false,
+        tools: Vec::new(),
+    };
+    let mut positionals = Vec::new();
+    let mut sizes = vec![(variant::DEFAULT.width, variant::DEFAULT.height)];
+    let mut color_types = vec![variant::DEFAULT.color_type];
+    let mut bit_depths = vec![variant::DEFAULT.bit_depth];
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            positionals.push(arg.clone());
+            continue;
+        }
+
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| iter.next().cloned())
+                .ok_or_else(|| format!("{} には値を指定してください", name))
+        };
+
+        match name {
+            "--output-format" => options.output_format = OutputFormat::parse(&value()?)?,
+            "--output" => options.output = Some(PathBuf::from(value()?)),
+            "--append" => options.append = true,
+            "--jobs" => match value()?.parse() {
+                Ok(jobs) if jobs > 0 => options.jobs = jobs,
+                _ => return Err("--jobs は1以上の整数で指定してください".into()),
+            },
+            "--seed" => {
+                options.seed = Some(value()?.parse().map_err(|_| "--seed は0以上の整数で指定してください")?)
+            },
+            "--details" => options.details = true,
+            "--sizes" => sizes = variant::parse_sizes(&value()?)?,
+            "--color-types" => color_types = variant::parse_color_types(&value()?)?,
+            "--bit-depths" => bit_depths = variant::parse_bit_depths(&value()?)?,
+            "--formats" => options.formats = formats::parse_list(&value()?)?,
+            "--qualities" => options.qualities = Some(formats::parse_qualities(&value()?)?),
+            "--ssimulacra2" => options.ssimulacra2 = true,
+            "--keep-samples" => {
+                options.keep_samples = value()?.parse().map_err(|_| "--keep-samples は0以上の整数で指定してください")?
+            },
+            "--strict-quality-check" => options.strict_quality_check = true,
+            "--target-size" => match value()?.parse() {
+                Ok(size) if size > 0 => set_target(&mut options, QualityTarget::Size(size))?,
+                _ => return Err("--target-size は1以上のバイト数で指定してください".into()),
+            },
+            "--target-ssim" => match value()?.parse() {
+                Ok(score) if score > 0.0 && score <= 1.0 => set_target(&mut options, QualityTarget::Ssim(score))?,
+                _ => return Err("--target-ssim は0より大きく1以下の値で指定してください".into()),
+            },
+            "--tool" => {
+                let value = value()?;
+                let (tool, path) = value.split_once('=').filter(|(tool, path)| !tool.is_empty() && !path.is_empty())
+                    .ok_or_else(|| format!("--tool は <名前>=<パス> で指定してください: {}", value))?;
+                options.tools.push((tool.to_string(), PathBuf::from(path)));
+            },
+            _ => return Err(format!("不明なオプションです: {}", name).into()),
+        }
+    }
+
+    options.variants = variant::matrix(&sizes, &color_types, &bit_depths);
+
+    Ok((options, positionals))
+}
+
+fn set_target(options: &mut Options, target: QualityTarget) -> Result<(), String> {
+    if options.target.is_some() {
+        return Err("--target-size と --target-ssim はどちらか一方を指定してください".to_string());
+    }
+    options.target = Some(target);
+    Ok(())
+}
+
+pub fn print_options_help() {
+    println!("  -h, --help                このヘルプメッセージを表示");
+    println!("  --output-format <形式>    結果の出力形式: csv, json, ndjson, parquet (デフォルト: csv)");
+    println!("  --output <パス>           結果の出力先パス (デフォルト: 結果ファイル名.<形式の拡張子>)");
+    println!("  --append                  既存の結果ファイルを上書きせず追記（列構成が異なる場合はエラー）");
+    println!("  --jobs <数>               画像の生成・変換を並列に実行するワーカー数 (デフォルト: 論理コア数)");
+    println!("  --seed <数値>             画像生成の乱数シード (デフォルト: ランダム)");
+    println!("  --details                 画像ごとの結果を <結果ファイル名>_details.<拡張子> に出力");
+    println!("  --sizes <一覧>            生成する画像の寸法をカンマ区切りで指定 (例: 128x128,4000x3000)");
+    println!("                            (thumbnails: 128x128,256x256,512x512 / photo: 4000x3000、デフォルト: 1024x1024)");
+    println!("  --color-types <一覧>      生成する画像の色の種類: gray, rgb, rgba (デフォルト: rgb)");
+    println!("  --bit-depths <一覧>       生成する画像のビット深度: 8, 16 (デフォルト: 8)");
+    println!("                            (寸法・色の種類・ビット深度は全ての組み合わせを比較)");
+    println!("  --formats <一覧>          比較するフォーマットをカンマ区切りで指定");
+    println!("                            (対応: {})", formats::names().join(", "));
+    println!(
+        "                            (デフォルト: {})",
+        formats::default_formats().iter().map(|format| format.name).collect::<Vec<_>>().join(", ")
+    );
+    println!("  --qualities <一覧>        比較する品質 (0〜100、例: 100,90,80 / 100-0/5 は100から0まで5刻み、0は最低品質1で変換)");
+    println!("  --ssimulacra2             品質ごとに知覚的な画質スコア SSIMULACRA2 を計算（100で完全一致）");
+    println!("  --keep-samples <数>       品質ごとに変換後の画像をこの枚数残し、元画像との差の熱マップも作成");
+    println!("                            (<結果ファイル名>_samples/<実行ID>/ に保存、1ラウンド目のみ)");
+    println!("  --strict-quality-check    品質の異なる出力がバイト単位で同じ場合に、警告ではなくエラーで終了");
+    println!("  --target-size <バイト>    画像ごとに、このサイズ以下になる最も高い品質を二分探索");
+    println!("  --target-ssim <値>        画像ごとに、SSIMがこの値以上になる最も低い品質を二分探索");
+    println!("  --tool <名前>=<パス>      外部コマンドのパスを指定 (対応: {}、環境変数 BENCH_TOOL_<名前> でも可)", tools::names(TOOLS).join(", "));
+}
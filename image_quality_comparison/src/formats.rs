@@ -0,0 +1,183 @@
+use tokio::process::Command;
+use benchmark_common::imagemagick::{self, Requirement};
+
+/// 品質を変えて比較する非可逆の画像フォーマット
+///
+/// `name` は結果の `format` 列と `--formats` で使う名前。同じ拡張子で設定違いのものを
+/// 同じディレクトリに置けるよう、名前が拡張子と異なる場合はファイル名に名前を含める。
+pub struct QualityFormat {
+    pub name: &'static str,
+    pub extension: &'static str,
+    /// ImageMagickの `-list format` での名前（デリゲートの確認に使う）
+    pub coder: &'static str,
+    /// `-quality` 以外に `convert` に渡すオプション
+    pub convert_args: &'static [&'static str],
+}
+
+pub static FORMATS: &[QualityFormat] = &[
+    QualityFormat { name: "webp", extension: "webp", coder: "WEBP", convert_args: &[] },
+    QualityFormat { name: "jpg", extension: "jpg", coder: "JPEG", convert_args: &["-sampling-factor", "4:2:0"] },
+    QualityFormat { name: "jpg-444", extension: "jpg", coder: "JPEG", convert_args: &["-sampling-factor", "4:4:4"] },
+    QualityFormat { name: "jpg-progressive", extension: "jpg", coder: "JPEG", convert_args: &["-sampling-factor", "4:2:0", "-interlace", "JPEG"] },
+    QualityFormat { name: "avif", extension: "avif", coder: "AVIF", convert_args: &[] },
+    QualityFormat { name: "jxl", extension: "jxl", coder: "JXL", convert_args: &[] },
+];
+
+/// ImageMagick で指定できる最低の品質（`-quality 0` は未指定として扱われ、既定の品質で圧縮される）
+pub const MIN_QUALITY: u32 = 1;
+
+/// `--formats` を省略した場合に比較するフォーマット
+///
+/// 標準的なImageMagickで扱えるものに限る。AVIF・JPEG XLは対応したImageMagickが必要なため、指定した場合のみ比較する。
+const DEFAULT_FORMATS: &[&str] = &["webp", "jpg", "jpg-444", "jpg-progressive"];
+
+impl QualityFormat {
+    /// `image_000_q80.webp` や `image_000_jpg-444_q80.jpg` のような出力ファイルのパス
+    pub fn file_path(&self, output_dir: &str, index: u32, quality: u32) -> String {
+        if self.name == self.extension {
+            format!("{}/image_{:03}_q{}.{}", output_dir, index, quality, self.extension)
+        } else {
+            format!("{}/image_{:03}_{}_q{}.{}", output_dir, index, self.name, quality, self.extension)
+        }
+    }
+
+    /// 元画像 `input` をこのフォーマットの品質 `quality` で `output` に変換するコマンド
+    pub fn encode_command(&self, input: &str, output: &str, quality: u32) -> Command {
+        let quality = effective_quality(quality).to_string();
+
+        let mut command = imagemagick::command();
+        command.arg(input).args(self.convert_args).args(["-quality", &quality]).arg(output);
+        command
+    }
+}
+
+/// 品質 `quality` を指定したときに実際にエンコーダーに渡す品質（0は最低品質 `MIN_QUALITY` にする）
+pub fn effective_quality(quality: u32) -> u32 {
+    quality.max(MIN_QUALITY)
+}
+
+pub fn default_formats() -> Vec<&'static QualityFormat> {
+    DEFAULT_FORMATS.iter().filter_map(|name| find(name)).collect()
+}
+
+pub fn find(name: &str) -> Option<&'static QualityFormat> {
+    FORMATS.iter().find(|format| format.name == name)
+}
+
+/// 比較に必要なImageMagickの対応フォーマット（元画像のPNGと、変換先の各フォーマット）
+///
+/// `decode` が真の場合は、画質を計算するため変換後の画像も読み込めなければならない。
+pub fn requirements(formats: &[&QualityFormat], decode: bool) -> Vec<Requirement> {
+    let mut requirements = vec![Requirement { coder: "PNG", read: true, write: true }];
+    requirements.extend(formats.iter().map(|format| Requirement { coder: format.coder, read: decode, write: true }));
+    requirements
+}
+
+pub fn names() -> Vec<&'static str> {
+    FORMATS.iter().map(|format| format.name).collect()
+}
+
+/// `webp,jpg,avif` のようなカンマ区切りの一覧を解釈する
+pub fn parse_list(value: &str) -> Result<Vec<&'static QualityFormat>, String> {
+    let mut formats = Vec::new();
+    for name in value.split(',').map(|name| name.trim().to_ascii_lowercase()).filter(|name| !name.is_empty()) {
+        let format = find(&name).ok_or_else(|| format!("未対応のフォーマットです: {} (対応: {})", name, names().join(", ")))?;
+        if !formats.iter().any(|f: &&QualityFormat| f.name == format.name) {
+            formats.push(format);
+        }
+    }
+
+    if formats.is_empty() {
+        return Err("--formats には1つ以上のフォーマットを指定してください".to_string());
+    }
+    Ok(formats)
+}
+
+/// `100,90,80` や `100-5/5`（100から5まで5刻み）を組み合わせた品質の一覧を解釈する
+///
+/// 品質は0から100の範囲。0は変換時に `effective_quality` で最低品質に置き換える。
+pub fn parse_qualities(value: &str) -> Result<Vec<u32>, String> {
+    let invalid = || format!("品質の指定が不正です: {} (例: 100,90,80 / 100-5/5)", value);
+
+    let mut qualities = Vec::new();
+    for item in value.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let values: Vec<u32> = match item.split_once('-') {
+            Some((start, rest)) => {
+                let (end, step) = rest.split_once('/').unwrap_or((rest, "1"));
+                let start: u32 = start.trim().parse().map_err(|_| invalid())?;
+                let end: u32 = end.trim().parse().map_err(|_| invalid())?;
+                let step: usize = step.trim().parse().ok().filter(|&step| step > 0).ok_or_else(invalid)?;
+                if start >= end {
+                    (end..=start).rev().step_by(step).collect()
+                } else {
+                    (start..=end).step_by(step).collect()
+                }
+            },
+            None => vec![item.parse().map_err(|_| invalid())?],
+        };
+
+        for quality in values {
+            if quality > 100 {
+                return Err(format!("品質は0から100の範囲で指定してください: {}", quality));
+            }
+            if !qualities.contains(&quality) {
+                qualities.push(quality);
+            }
+        }
+    }
+
+    if qualities.is_empty() {
+        return Err(invalid());
+    }
+    Ok(qualities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_qualities_accepts_values_and_ranges() {
+        assert_eq!(parse_qualities("100,90,80").unwrap(), vec![100, 90, 80]);
+        assert_eq!(parse_qualities("100-80/10").unwrap(), vec![100, 90, 80]);
+        assert_eq!(parse_qualities("80-100/10").unwrap(), vec![80, 90, 100]);
+        assert_eq!(parse_qualities("3-1").unwrap(), vec![3, 2, 1]);
+        // 刻みで終点に届かない場合は終点を含めない
+        assert_eq!(parse_qualities("100-1/50").unwrap(), vec![100, 50]);
+        assert_eq!(parse_qualities("1-100/50").unwrap(), vec![1, 51]);
+        assert_eq!(parse_qualities("50").unwrap(), vec![50]);
+    }
+
+    #[test]
+    fn parse_qualities_removes_duplicates_in_order() {
+        assert_eq!(parse_qualities("100,95,90-80/5, 100").unwrap(), vec![100, 95, 90, 85, 80]);
+        assert_eq!(parse_qualities(" 90 ,, 80 ").unwrap(), vec![90, 80]);
+    }
+
+    #[test]
+    fn parse_qualities_accepts_zero_and_rejects_out_of_range() {
+        assert_eq!(parse_qualities("0").unwrap(), vec![0]);
+        assert_eq!(parse_qualities("20-0/5").unwrap(), vec![20, 15, 10, 5, 0]);
+        assert_eq!(parse_qualities("100-0/5").unwrap().len(), 21);
+        assert!(parse_qualities("101").is_err());
+        assert!(parse_qualities("90-110/10").is_err());
+    }
+
+    #[test]
+    fn zero_quality_is_encoded_at_minimum() {
+        assert_eq!(effective_quality(0), MIN_QUALITY);
+        assert_eq!(effective_quality(1), 1);
+        assert_eq!(effective_quality(100), 100);
+
+        let command = find("webp").unwrap().encode_command("in.png", "out.webp", 0);
+        let args: Vec<_> = command.as_std().get_args().collect();
+        assert_eq!(args, ["in.png", "-quality", "1", "out.webp"]);
+    }
+
+    #[test]
+    fn parse_qualities_rejects_malformed_input() {
+        for value in ["", ",", "abc", "-5", "90-", "100-50/0", "100-50/x", "1.5"] {
+            assert!(parse_qualities(value).is_err(), "{:?} を受け付けた", value);
+        }
+    }
+}
@@ -1,53 +1,125 @@
+mod cli;
+mod formats;
+mod metrics;
+mod samples;
+mod selfcheck;
+mod target;
+mod variant;
+
 use std::fs;
-use std::process::Command;
-use csv::Writer;
 use serde::Serialize;
 use rand::{thread_rng, Rng};
-use tokio::task::JoinSet;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::env;
+use std::path::Path;
+use std::time::Instant;
+use cli::Options;
+use formats::QualityFormat;
+use manifest::RunManifest;
+use benchmark_common::{imagemagick, interrupt, manifest, pool, tools};
+use benchmark_common::output::{self, ResultWriter};
+use target::QualityTarget;
+use variant::ImageVariant;
 
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 struct ImageQualityStats {
+    run_id: String,
     run_number: u32,
+    format: String,
+    width: u32,
+    height: u32,
+    channels: u8,
+    bit_depth: u8,
     quality: u32,
+    /// 実際にエンコーダーに渡した品質（品質0は最低品質で変換する）
+    effective_quality: u32,
     total_size: u64,
     average_size: f64,
     min_size: u64,
     max_size: u64,
     median_size: f64,
     compression_ratio: f64,
+    /// 全画像のSSIMULACRA2の平均（以下2列は `--ssimulacra2` を指定しない場合は空）
+    ssimulacra2_mean: Option<f64>,
+    /// 最も画質の悪かった画像のSSIMULACRA2
+    ssimulacra2_min: Option<f64>,
+    /// 出力が全画像でバイト単位に同じだった、先に変換した品質（`-quality` が効いていない疑い）
+    identical_to_quality: Option<u32>,
+}
+
+/// `--details` で記録する、画像ごとの結果
+#[derive(Serialize, Default)]
+struct ImageDetail {
+    run_id: String,
+    run_number: u32,
+    image_index: u32,
+    seed: u64,
+    format: String,
+    width: u32,
+    height: u32,
+    channels: u8,
+    bit_depth: u8,
+    quality: u32,
+    effective_quality: u32,
+    size: u64,
+    encode_seconds: f64,
+    ssimulacra2: Option<f64>,
+}
+
+/// `--target-size` / `--target-ssim` で記録する、画像ごとの探索結果
+#[derive(Serialize, Default)]
+struct TargetQualityResult {
+    run_id: String,
+    run_number: u32,
+    image_index: u32,
+    format: String,
+    width: u32,
+    height: u32,
+    channels: u8,
+    bit_depth: u8,
+    target: String,
+    target_value: f64,
+    quality: u32,
+    size: u64,
+    ssim: Option<f64>,
+    iterations: u32,
+    target_met: bool,
 }
 
 fn print_help() {
     println!("画像品質比較ベンチマーク");
-    println!("");
+    println!();
     println!("使用方法:");
     println!("  {} [画像枚数] [ラウンド数]", env::args().next().unwrap_or_else(|| "program".to_string()));
-    println!("");
+    println!();
     println!("引数:");
     println!("  画像枚数    各ラウンドで生成する画像の枚数 (デフォルト: 100)");
     println!("  ラウンド数  ベンチマークの実行回数 (デフォルト: 10)");
-    println!("");
+    println!();
     println!("オプション:");
-    println!("  -h, --help  このヘルプメッセージを表示");
-    println!("");
+    cli::print_options_help();
+    println!();
     println!("例:");
     println!("  {}           # デフォルト: 100枚、10ラウンド", env::args().next().unwrap_or_else(|| "program".to_string()));
     println!("  {} 50        # 50枚、10ラウンド", env::args().next().unwrap_or_else(|| "program".to_string()));
     println!("  {} 200 5     # 200枚、5ラウンド", env::args().next().unwrap_or_else(|| "program".to_string()));
-    println!("");
-    println!("品質設定: PNG→WebP変換で100%から50%まで10%刻みで品質比較を実行");
+    println!("  {} 100 10 --output-format ndjson  # JSON Lines で出力", env::args().next().unwrap_or_else(|| "program".to_string()));
+    println!("  {} 100 10 --formats jpg,avif --qualities 100-10/10  # JPEG・AVIFを100%から10%まで10%刻みで比較", env::args().next().unwrap_or_else(|| "program".to_string()));
+    println!("  {} 100 3 --target-size 200000                      # 画像ごとに200KB以下になる最高品質を探索", env::args().next().unwrap_or_else(|| "program".to_string()));
+    println!();
+    println!("品質設定: PNG→各フォーマット変換で、デフォルトでは100%から50%まで10%刻みで品質比較を実行");
 }
 
-fn parse_args() -> Result<(u32, u32), Box<dyn std::error::Error>> {
+fn parse_args() -> Result<(u32, u32, Options), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
     
-    if args.len() > 1 && (args[1] == "--help" || args[1] == "-h") {
+    if args.iter().skip(1).any(|arg| arg == "--help" || arg == "-h") {
         print_help();
         std::process::exit(0);
     }
     
+    let (options, args) = cli::split_options(&args)?;
+    
     let image_count = if args.len() > 1 {
         args[1].parse::<u32>()
             .map_err(|_| "画像枚数は正の整数で指定してください")?
@@ -70,151 +142,456 @@ fn parse_args() -> Result<(u32, u32), Box<dyn std::error::Error>> {
         return Err("ラウンド数は1以上で指定してください".into());
     }
     
-    Ok((image_count, rounds))
+    Ok((image_count, rounds, options))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let (image_count, rounds) = parse_args()?;
+    let (image_count, rounds, options) = parse_args()?;
+    interrupt::install()?;
+    
+    // 外部コマンドのパスとバージョンを決める（--tool、環境変数 BENCH_TOOL_<名前>、PATH の順）
+    tools::resolve(cli::TOOLS, &options.tools)?;
+    
+    // 画像を生成する前に、ImageMagickが全フォーマットを読み書きできるか確認
+    let decode = options.ssimulacra2 || options.keep_samples > 0 || matches!(options.target, Some(QualityTarget::Ssim(_)));
+    imagemagick::preflight(&formats::requirements(&options.formats, decode))?;
+    
+    if let Some(target) = options.target {
+        return run_target_search(image_count, rounds, options, target).await;
+    }
     
     println!("画像品質比較ベンチマーク開始: {}枚の画像で{}ラウンド実行", image_count, rounds);
-    println!("品質設定: 100%から50%まで10%刻みで比較");
+    println!("並列数: {}", options.jobs);
     
-    let mut csv_writer = Writer::from_path("image_quality_comparison_results.csv")?;
+    let seed = options.seed.unwrap_or_else(|| thread_rng().r#gen());
+    println!("シード: {}", seed);
     
-    // 品質設定（100%から50%まで10%刻み）
-    let quality_levels = [100, 90, 80, 70, 60, 50];
+    let output_path = options.output.clone()
+        .unwrap_or_else(|| output::default_path("image_quality_comparison_results", options.output_format));
+    let mut result_writer = ResultWriter::create::<ImageQualityStats>(&output_path, options.output_format, options.append)?;
+    let details_path = output::details_path(&output_path);
+    let mut details_writer = if options.details {
+        Some(ResultWriter::create::<ImageDetail>(&details_path, options.output_format, options.append)?)
+    } else {
+        None
+    };
     
-    for run in 1..=rounds {
+    // 実行環境を結果ファイルと同じ場所に記録
+    let manifest = RunManifest::collect(&output_path, Path::new("."), tools::resolved());
+    let manifest_path = manifest.write_next_to(&output_path, options.append)?;
+    println!("実行ID: {}", manifest.run_id);
+    
+    // 品質設定（デフォルトは100%から50%まで10%刻み）
+    let quality_levels = options.qualities.clone().unwrap_or_else(|| vec![100, 90, 80, 70, 60, 50]);
+    
+    println!("品質設定: {}", quality_levels.iter().map(|q| format!("{}%", q)).collect::<Vec<_>>().join(", "));
+    if quality_levels.contains(&0) {
+        println!("品質0はImageMagickでは未指定として扱われるため、最低品質の{}で変換します（effective_quality 列）", formats::MIN_QUALITY);
+    }
+    println!("フォーマット: {}", options.formats.iter().map(|format| format.name).collect::<Vec<_>>().join(", "));
+    println!("画像: {}", options.variants.iter().map(ImageVariant::label).collect::<Vec<_>>().join(", "));
+    
+    // 見た目を確認できるよう残すサンプル画像の保存先
+    let samples_root = samples::samples_root(&output_path, &manifest.run_id);
+    let sample_count = options.keep_samples.min(image_count);
+    
+    'rounds: for run in 1..=rounds {
+        if interrupt::is_interrupted() {
+            break;
+        }
+        
         println!("実行回数: {}/{}", run, rounds);
         
         let output_dir = format!("images_run_{}", run);
         fs::create_dir_all(&output_dir)?;
         
-        // ランダムなPNG画像を生成（4並列）
-        generate_random_png_images_parallel(&output_dir, image_count).await?;
-        println!("PNG画像{}枚を生成しました", image_count);
-        
-        // 各品質レベルでWebP変換（4並列）
-        for &quality in &quality_levels {
-            convert_png_to_webp_parallel(&output_dir, quality, image_count).await?;
-            println!("品質{}%でWebP変換しました", quality);
+        // 寸法・色の種類・ビット深度の組み合わせごとに、画像の生成から統計までを行う
+        for variant in &options.variants {
+            // ランダムなPNG画像を生成（--jobs 並列）
+            generate_random_png_images_parallel(&output_dir, variant, image_count, seed, run, options.jobs).await?;
+            println!("PNG画像{}枚を生成しました（{}）", image_count, variant.label());
             
-            // 各品質の統計を計算
-            let png_stats = calculate_png_stats(&output_dir, run, image_count)?;
-            let webp_stats = calculate_webp_quality_stats(&output_dir, quality, run, image_count, png_stats.total_size)?;
+            let png_stats = calculate_png_stats(&output_dir, &manifest.run_id, variant, run, image_count)?;
             
-            // CSV出力（WebPの結果のみ）
-            csv_writer.serialize(&webp_stats)?;
+            // サンプル画像は1ラウンド目の画像から残す
+            let samples_dir = (run == 1 && sample_count > 0).then(|| samples::variant_dir(&samples_root, variant));
+            if let Some(samples_dir) = &samples_dir {
+                samples::keep_sources(&output_dir, samples_dir, sample_count)?;
+            }
+            
+            // 各フォーマット・各品質レベルで変換（--jobs 並列）
+            for format in &options.formats {
+                // 品質ごとの出力のハッシュ（品質の設定が効いているかの確認用）
+                let mut digests: Vec<(u32, Vec<u64>)> = Vec::new();
+                let mut delegates_reported = false;
+                
+                for &quality in &quality_levels {
+                    let encode_seconds = convert_png_parallel(&output_dir, format, quality, image_count, options.jobs).await?;
+                    println!("品質{}%で{}に変換しました", quality, format.name.to_uppercase());
+                    
+                    // 元画像と比べた知覚的な画質（--jobs 並列）
+                    let scores = if options.ssimulacra2 {
+                        score_ssimulacra2_parallel(&output_dir, format, quality, image_count, options.jobs).await?
+                    } else {
+                        vec![None; image_count as usize]
+                    };
+                    
+                    // 中断された場合は途中までの画像で統計を取らずに終了処理へ進む
+                    if interrupt::is_interrupted() {
+                        break 'rounds;
+                    }
+                    
+                    // 各品質の統計を計算
+                    let mut stats = calculate_quality_stats(&output_dir, format, quality, image_count, &png_stats)?;
+                    let measured: Vec<f64> = scores.iter().flatten().copied().collect();
+                    if !measured.is_empty() {
+                        stats.ssimulacra2_mean = Some(measured.iter().sum::<f64>() / measured.len() as f64);
+                        stats.ssimulacra2_min = measured.iter().copied().reduce(f64::min);
+                        println!(
+                            "{} 品質{}%: SSIMULACRA2 平均 {:.2}、最低 {:.2}",
+                            format.name.to_uppercase(),
+                            quality,
+                            stats.ssimulacra2_mean.unwrap_or_default(),
+                            stats.ssimulacra2_min.unwrap_or_default()
+                        );
+                    }
+                    
+                    // 品質の設定が違うのに出力が同じなら、デリゲートが -quality を無視・丸めている疑いがある
+                    let digest = selfcheck::output_digest(&output_dir, format, quality, image_count)?;
+                    // 品質0と最低品質のように同じ品質で変換したものは比べない
+                    let identical = digests.iter()
+                        .find(|(other, other_digest)| {
+                            *other_digest == digest && formats::effective_quality(*other) != formats::effective_quality(quality)
+                        })
+                        .map(|(other, _)| *other);
+                    if let Some(other) = identical {
+                        stats.identical_to_quality = Some(other);
+                        let message = format!(
+                            "{} 品質{}%の出力が品質{}%とバイト単位で同じです。ImageMagickのデリゲートが -quality を無視または丸めている可能性があります",
+                            format.name.to_uppercase(),
+                            quality,
+                            other
+                        );
+                        eprintln!("{}: {}", if options.strict_quality_check { "エラー" } else { "警告" }, message);
+                        // 使われているImageMagickとデリゲートのバージョンは、フォーマットごとに最初の1回だけ表示する
+                        if !delegates_reported {
+                            for line in selfcheck::delegate_report(format) {
+                                eprintln!("  {}", line);
+                            }
+                            delegates_reported = true;
+                        }
+                    }
+                    digests.push((quality, digest));
+                    
+                    // サンプル画像と、元画像との差の熱マップを残す
+                    if let Some(samples_dir) = &samples_dir {
+                        samples::keep_outputs(&output_dir, samples_dir, format, quality, sample_count).await?;
+                    }
+                    
+                    // 結果出力（変換後のフォーマットの結果のみ）
+                    result_writer.serialize(&stats)?;
+                    
+                    // 画像ごとの結果を出力
+                    if let Some(details_writer) = details_writer.as_mut() {
+                        for i in 0..image_count {
+                            details_writer.serialize(ImageDetail {
+                                run_id: manifest.run_id.clone(),
+                                run_number: run,
+                                image_index: i,
+                                seed: image_seed(seed, run, i),
+                                format: format.name.to_uppercase(),
+                                width: variant.width,
+                                height: variant.height,
+                                channels: variant.color_type.channels(),
+                                bit_depth: variant.bit_depth,
+                                quality,
+                                effective_quality: formats::effective_quality(quality),
+                                size: fs::metadata(format.file_path(&output_dir, i, quality))?.len(),
+                                encode_seconds: encode_seconds[i as usize],
+                                ssimulacra2: scores[i as usize],
+                            })?;
+                        }
+                    }
+                    
+                    // --strict-quality-check では、同じ出力だった品質の行まで保存してから終了する
+                    if options.strict_quality_check && stats.identical_to_quality.is_some() {
+                        result_writer.flush()?;
+                        if let Some(details_writer) = details_writer.as_mut() {
+                            details_writer.flush()?;
+                        }
+                        cleanup_remaining_files(rounds)?;
+                        return Err("品質の異なる出力が同じだったため終了しました（--strict-quality-check）".into());
+                    }
+                }
+            }
+            
+            // 次の組み合わせの画像と混ざらないよう、画像ファイルを削除
+            cleanup_images(&output_dir, image_count, &quality_levels)?;
         }
         
-        // 画像ファイルを削除
-        cleanup_images(&output_dir, image_count, &quality_levels)?;
         fs::remove_dir(&output_dir)?;
         println!("実行 {} 完了（画像ファイル削除済み）", run);
     }
     
-    csv_writer.flush()?;
+    result_writer.flush()?;
+    if let Some(details_writer) = details_writer.as_mut() {
+        details_writer.flush()?;
+    }
     
     // 実験終了後の最終クリーンアップ
     cleanup_remaining_files(rounds)?;
-    println!("全ての実行が完了しました。結果はimage_quality_comparison_results.csvに保存されました。");
+    
+    if interrupt::is_interrupted() {
+        println!("中断されました。完了した分の結果は{}に保存されました。", output_path.display());
+        std::process::exit(interrupt::EXIT_INTERRUPTED);
+    }
+    
+    println!("全ての実行が完了しました。結果は{}に保存されました。", output_path.display());
+    if options.details {
+        println!("画像ごとの結果は{}に保存されました。", details_path.display());
+    }
+    if sample_count > 0 {
+        println!("サンプル画像は{}に保存されました。", samples_root.display());
+    }
+    println!("実行環境は{}に保存されました。", manifest_path.display());
     
     Ok(())
 }
 
-async fn generate_random_png_images_parallel(output_dir: &str, count: u32) -> Result<(), Box<dyn std::error::Error>> {
-    let mut join_set = JoinSet::new();
-    let output_dir = Arc::new(output_dir.to_string());
+/// 画像ごと・フォーマットごとに、目標のサイズまたはSSIMを満たす品質を二分探索する
+async fn run_target_search(image_count: u32, rounds: u32, options: Options, target: QualityTarget) -> Result<(), Box<dyn std::error::Error>> {
+    println!("目標品質の探索開始: {}枚の画像で{}ラウンド実行", image_count, rounds);
+    match target {
+        QualityTarget::Size(size) => println!("目標: ファイルサイズ {} bytes 以下", size),
+        QualityTarget::Ssim(score) => println!("目標: SSIM {} 以上", score),
+    }
+    println!("フォーマット: {}", options.formats.iter().map(|format| format.name).collect::<Vec<_>>().join(", "));
+    println!("画像: {}", options.variants.iter().map(ImageVariant::label).collect::<Vec<_>>().join(", "));
+    println!("並列数: {}", options.jobs);
+    
+    let seed = options.seed.unwrap_or_else(|| thread_rng().r#gen());
+    println!("シード: {}", seed);
+    
+    let output_path = options.output.clone()
+        .unwrap_or_else(|| output::default_path("image_quality_comparison_target_results", options.output_format));
+    let mut result_writer = ResultWriter::create::<TargetQualityResult>(&output_path, options.output_format, options.append)?;
     
-    let chunk_size = count / 4;
-    for thread_id in 0..4 {
-        let start = thread_id * chunk_size;
-        let end = if thread_id == 3 { count } else { (thread_id + 1) * chunk_size };
-        let output_dir_clone = Arc::clone(&output_dir);
+    // 実行環境を結果ファイルと同じ場所に記録
+    let manifest = RunManifest::collect(&output_path, Path::new("."), tools::resolved());
+    let manifest_path = manifest.write_next_to(&output_path, options.append)?;
+    println!("実行ID: {}", manifest.run_id);
+    
+    'rounds: for run in 1..=rounds {
+        if interrupt::is_interrupted() {
+            break;
+        }
+        
+        println!("実行回数: {}/{}", run, rounds);
         
-        join_set.spawn(async move {
-            let mut rng = thread_rng();
-            for i in start..end {
-                let r: u8 = rng.r#gen();
-                let g: u8 = rng.r#gen();
-                let b: u8 = rng.r#gen();
+        let output_dir = format!("images_run_{}", run);
+        fs::create_dir_all(&output_dir)?;
+        
+        for variant in &options.variants {
+            generate_random_png_images_parallel(&output_dir, variant, image_count, seed, run, options.jobs).await?;
+            println!("PNG画像{}枚を生成しました（{}）", image_count, variant.label());
+            
+            for format in &options.formats {
+                let results = search_target_quality_parallel(&output_dir, format, target, image_count, options.jobs).await?;
                 
-                let output_path = format!("{}/image_{:03}.png", output_dir_clone.as_str(), i);
+                // 中断された場合は全画像がそろっていないため記録しない
+                if interrupt::is_interrupted() {
+                    break 'rounds;
+                }
                 
-                let status = Command::new("convert")
-                    .args(&[
-                        "-size", "1024x1024",
-                        &format!("xc:rgb({},{},{})", r, g, b),
-                        "+noise", "Random",
-                        &output_path
-                    ])
-                    .status()
-                    .map_err(|e| format!("画像生成コマンド実行エラー: {}", e))?;
-                    
-                if !status.success() {
-                    return Err(format!("画像生成に失敗しました: {}", output_path));
+                let count = results.len() as f64;
+                println!(
+                    "{} {}: 平均品質 {:.1}、平均サイズ {:.0} bytes、平均反復 {:.1}回、目標達成 {}/{}枚",
+                    format.name.to_uppercase(),
+                    variant.label(),
+                    results.iter().map(|(_, r)| r.quality as f64).sum::<f64>() / count,
+                    results.iter().map(|(_, r)| r.size as f64).sum::<f64>() / count,
+                    results.iter().map(|(_, r)| r.iterations as f64).sum::<f64>() / count,
+                    results.iter().filter(|(_, r)| r.target_met).count(),
+                    results.len()
+                );
+                
+                for (image_index, result) in results {
+                    result_writer.serialize(TargetQualityResult {
+                        run_id: manifest.run_id.clone(),
+                        run_number: run,
+                        image_index,
+                        format: format.name.to_uppercase(),
+                        width: variant.width,
+                        height: variant.height,
+                        channels: variant.color_type.channels(),
+                        bit_depth: variant.bit_depth,
+                        target: target.kind().to_string(),
+                        target_value: target.value(),
+                        quality: result.quality,
+                        size: result.size,
+                        ssim: result.ssim,
+                        iterations: result.iterations,
+                        target_met: result.target_met,
+                    })?;
                 }
             }
-            Ok::<(), String>(())
-        });
+            
+            // 探索中の画像は画像ごとに削除済みのため、元画像のPNGのみ残っている
+            cleanup_images(&output_dir, image_count, &[])?;
+        }
+        
+        fs::remove_dir(&output_dir)?;
+        println!("実行 {} 完了（画像ファイル削除済み）", run);
     }
     
-    while let Some(result) = join_set.join_next().await {
-        match result {
-            Ok(Ok(())) => {},
-            Ok(Err(e)) => return Err(e.into()),
-            Err(e) => return Err(format!("並列実行エラー: {}", e).into()),
-        }
+    result_writer.flush()?;
+    
+    // 実験終了後の最終クリーンアップ
+    cleanup_remaining_files(rounds)?;
+    
+    if interrupt::is_interrupted() {
+        println!("中断されました。完了した分の結果は{}に保存されました。", output_path.display());
+        std::process::exit(interrupt::EXIT_INTERRUPTED);
     }
     
+    println!("全ての実行が完了しました。結果は{}に保存されました。", output_path.display());
+    println!("実行環境は{}に保存されました。", manifest_path.display());
+    
     Ok(())
 }
 
-async fn convert_png_to_webp_parallel(output_dir: &str, quality: u32, count: u32) -> Result<(), Box<dyn std::error::Error>> {
-    let mut join_set = JoinSet::new();
+/// 全画像について目標品質を探索し、画像番号順に結果を返す（--jobs 並列）
+async fn search_target_quality_parallel(
+    output_dir: &str,
+    format: &'static QualityFormat,
+    target: QualityTarget,
+    count: u32,
+    jobs: usize,
+) -> Result<Vec<(u32, target::SearchResult)>, Box<dyn std::error::Error>> {
+    let output_dir = Arc::new(output_dir.to_string());
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results_clone = Arc::clone(&results);
+    
+    pool::for_each_image(count, jobs, move |i| {
+        let output_dir = Arc::clone(&output_dir);
+        let results = Arc::clone(&results_clone);
+        
+        async move {
+            let input_path = format!("{}/image_{:03}.png", output_dir.as_str(), i);
+            let search_path = format!("{}/image_{:03}_{}_search.{}", output_dir.as_str(), i, format.name, format.extension);
+            
+            let result = target::search(&input_path, &search_path, format, target).await?;
+            let _ = fs::remove_file(&search_path);
+            
+            results.lock().unwrap().push((i, result));
+            Ok(())
+        }
+    })
+    .await?;
+    
+    let mut results = std::mem::take(&mut *results.lock().unwrap());
+    results.sort_by_key(|(i, _)| *i);
+    Ok(results)
+}
+
+/// 画像ごとのシード。ラウンドごとに異なる画像になるよう、ラウンド番号を上位32bitに加える
+fn image_seed(seed: u64, run: u32, index: u32) -> u64 {
+    seed.wrapping_add((run as u64) << 32).wrapping_add(index as u64)
+}
+
+async fn generate_random_png_images_parallel(
+    output_dir: &str,
+    variant: &ImageVariant,
+    count: u32,
+    seed: u64,
+    run: u32,
+    jobs: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
     let output_dir = Arc::new(output_dir.to_string());
+    let variant = *variant;
     
-    let chunk_size = count / 4;
-    for thread_id in 0..4 {
-        let start = thread_id * chunk_size;
-        let end = if thread_id == 3 { count } else { (thread_id + 1) * chunk_size };
-        let output_dir_clone = Arc::clone(&output_dir);
+    pool::for_each_image(count, jobs, move |i| {
+        let output_dir = Arc::clone(&output_dir);
         
-        join_set.spawn(async move {
-            for i in start..end {
-                let input_path = format!("{}/image_{:03}.png", output_dir_clone.as_str(), i);
-                let output_path = format!("{}/image_{:03}_q{}.webp", output_dir_clone.as_str(), i, quality);
+        async move {
+            let output_path = format!("{}/image_{:03}.png", output_dir.as_str(), i);
+            
+            let status = variant.generate_command(image_seed(seed, run, i), &output_path)
+                .status()
+                .await
+                .map_err(|e| format!("画像生成コマンド実行エラー: {}", e))?;
                 
-                let status = Command::new("convert")
-                    .args(&[
-                        &input_path,
-                        "-quality", &quality.to_string(),
-                        &output_path
-                    ])
-                    .status()
-                    .map_err(|e| format!("変換コマンド実行エラー: {}", e))?;
-                    
-                if !status.success() {
-                    return Err(format!("画像変換に失敗しました: {} -> {}", input_path, output_path));
-                }
+            if !status.success() {
+                return Err(format!("画像生成に失敗しました: {}", output_path));
             }
-            Ok::<(), String>(())
-        });
-    }
+            Ok(())
+        }
+    })
+    .await
+}
+
+/// 全画像を品質 `quality` で変換し、画像ごとの変換時間（秒）を画像番号順に返す
+async fn convert_png_parallel(output_dir: &str, format: &'static QualityFormat, quality: u32, count: u32, jobs: usize) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let output_dir = Arc::new(output_dir.to_string());
+    let encode_seconds = Arc::new(Mutex::new(vec![0.0; count as usize]));
+    let encode_seconds_clone = Arc::clone(&encode_seconds);
     
-    while let Some(result) = join_set.join_next().await {
-        match result {
-            Ok(Ok(())) => {},
-            Ok(Err(e)) => return Err(e.into()),
-            Err(e) => return Err(format!("並列実行エラー: {}", e).into()),
+    pool::for_each_image(count, jobs, move |i| {
+        let output_dir = Arc::clone(&output_dir);
+        let encode_seconds = Arc::clone(&encode_seconds_clone);
+        
+        async move {
+            let input_path = format!("{}/image_{:03}.png", output_dir.as_str(), i);
+            let output_path = format.file_path(&output_dir, i, quality);
+            
+            let start = Instant::now();
+            let status = format.encode_command(&input_path, &output_path, quality)
+                .status()
+                .await
+                .map_err(|e| format!("変換コマンド実行エラー: {}", e))?;
+                
+            if !status.success() {
+                return Err(format!("画像変換に失敗しました: {} -> {}", input_path, output_path));
+            }
+            encode_seconds.lock().unwrap()[i as usize] = start.elapsed().as_secs_f64();
+            Ok(())
         }
-    }
+    })
+    .await?;
     
-    Ok(())
+    let encode_seconds = std::mem::take(&mut *encode_seconds.lock().unwrap());
+    Ok(encode_seconds)
 }
 
-fn calculate_png_stats(output_dir: &str, run_number: u32, image_count: u32) -> Result<ImageQualityStats, Box<dyn std::error::Error>> {
+/// 品質 `quality` で変換した全画像のSSIMULACRA2を、画像番号順に返す（求められない画像は `None`）
+async fn score_ssimulacra2_parallel(output_dir: &str, format: &'static QualityFormat, quality: u32, count: u32, jobs: usize) -> Result<Vec<Option<f64>>, Box<dyn std::error::Error>> {
+    let output_dir = Arc::new(output_dir.to_string());
+    let scores = Arc::new(Mutex::new(vec![None; count as usize]));
+    let scores_clone = Arc::clone(&scores);
+    
+    pool::for_each_image(count, jobs, move |i| {
+        let output_dir = Arc::clone(&output_dir);
+        let scores = Arc::clone(&scores_clone);
+        
+        async move {
+            let source = metrics::decode_rgb(&format!("{}/image_{:03}.png", output_dir.as_str(), i)).await?;
+            let distorted = metrics::decode_rgb(&format.file_path(&output_dir, i, quality)).await?;
+            // スコアの計算はCPUを使い続けるため、非同期のワーカーを止めないよう別スレッドで行う
+            let score = tokio::task::spawn_blocking(move || metrics::ssimulacra2(&source, &distorted))
+                .await
+                .map_err(|e| format!("SSIMULACRA2の計算に失敗しました: {}", e))?;
+            scores.lock().unwrap()[i as usize] = score;
+            Ok(())
+        }
+    })
+    .await?;
+    
+    let scores = std::mem::take(&mut *scores.lock().unwrap());
+    Ok(scores)
+}
+
+fn calculate_png_stats(output_dir: &str, run_id: &str, variant: &ImageVariant, run_number: u32, image_count: u32) -> Result<ImageQualityStats, Box<dyn std::error::Error>> {
     let mut sizes: Vec<u64> = Vec::new();
     
     for i in 0..image_count {
@@ -230,7 +607,7 @@ fn calculate_png_stats(output_dir: &str, run_number: u32, image_count: u32) -> R
     let min_size = *sizes.first().unwrap();
     let max_size = *sizes.last().unwrap();
     
-    let median_size = if sizes.len() % 2 == 0 {
+    let median_size = if sizes.len().is_multiple_of(2) {
         let mid = sizes.len() / 2;
         (sizes[mid - 1] + sizes[mid]) as f64 / 2.0
     } else {
@@ -238,22 +615,33 @@ fn calculate_png_stats(output_dir: &str, run_number: u32, image_count: u32) -> R
     };
     
     Ok(ImageQualityStats {
+        run_id: run_id.to_string(),
         run_number,
+        format: "PNG".to_string(),
+        width: variant.width,
+        height: variant.height,
+        channels: variant.color_type.channels(),
+        bit_depth: variant.bit_depth,
         quality: 100, // PNG is lossless
+        effective_quality: 100,
         total_size,
         average_size,
         min_size,
         max_size,
         median_size,
         compression_ratio: 1.0, // baseline
+        ssimulacra2_mean: None,
+        ssimulacra2_min: None,
+        identical_to_quality: None,
     })
 }
 
-fn calculate_webp_quality_stats(output_dir: &str, quality: u32, run_number: u32, image_count: u32, png_total_size: u64) -> Result<ImageQualityStats, Box<dyn std::error::Error>> {
+/// 品質 `quality` で変換した画像の統計を、同じ画像群のPNGの統計 `png_stats` を基準に計算する
+fn calculate_quality_stats(output_dir: &str, format: &QualityFormat, quality: u32, image_count: u32, png_stats: &ImageQualityStats) -> Result<ImageQualityStats, Box<dyn std::error::Error>> {
     let mut sizes: Vec<u64> = Vec::new();
     
     for i in 0..image_count {
-        let file_path = format!("{}/image_{:03}_q{}.webp", output_dir, i, quality);
+        let file_path = format.file_path(output_dir, i, quality);
         let metadata = fs::metadata(&file_path)?;
         sizes.push(metadata.len());
     }
@@ -265,24 +653,35 @@ fn calculate_webp_quality_stats(output_dir: &str, quality: u32, run_number: u32,
     let min_size = *sizes.first().unwrap();
     let max_size = *sizes.last().unwrap();
     
-    let median_size = if sizes.len() % 2 == 0 {
+    let median_size = if sizes.len().is_multiple_of(2) {
         let mid = sizes.len() / 2;
         (sizes[mid - 1] + sizes[mid]) as f64 / 2.0
     } else {
         sizes[sizes.len() / 2] as f64
     };
     
-    let compression_ratio = total_size as f64 / png_total_size as f64;
+    let compression_ratio = total_size as f64 / png_stats.total_size as f64;
     
     Ok(ImageQualityStats {
-        run_number,
+        run_id: png_stats.run_id.clone(),
+        run_number: png_stats.run_number,
+        format: format.name.to_uppercase(),
+        width: png_stats.width,
+        height: png_stats.height,
+        channels: png_stats.channels,
+        bit_depth: png_stats.bit_depth,
         quality,
+        effective_quality: formats::effective_quality(quality),
         total_size,
         average_size,
         min_size,
         max_size,
         median_size,
         compression_ratio,
+        // 画質は呼び出し側で設定する
+        ssimulacra2_mean: None,
+        ssimulacra2_min: None,
+        identical_to_quality: None,
     })
 }
 
@@ -295,12 +694,14 @@ fn cleanup_images(output_dir: &str, image_count: u32, quality_levels: &[u32]) ->
         }
     }
     
-    // WebP files
-    for &quality in quality_levels {
-        for i in 0..image_count {
-            let file_path = format!("{}/image_{:03}_q{}.webp", output_dir, i, quality);
-            if fs::metadata(&file_path).is_ok() {
-                fs::remove_file(&file_path)?;
+    // 変換後のファイル（今回比較しなかったフォーマットも、以前の実行の残りがあれば削除する）
+    for format in formats::FORMATS {
+        for &quality in quality_levels {
+            for i in 0..image_count {
+                let file_path = format.file_path(output_dir, i, quality);
+                if fs::metadata(&file_path).is_ok() {
+                    fs::remove_file(&file_path)?;
+                }
             }
         }
     }
@@ -0,0 +1,213 @@
+use ssimulacra2::{ColorPrimaries, Rgb, TransferCharacteristic};
+use benchmark_common::imagemagick;
+
+/// 8bitグレースケールの画像
+pub struct GrayImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// sRGBのRGB画像（各チャンネル0〜1）
+pub struct RgbImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+/// 画像をImageMagickで輝度のみの8bitグレースケールにデコードする
+pub async fn decode_gray(path: &str) -> Result<GrayImage, String> {
+    let output = imagemagick::command()
+        .args([path, "-colorspace", "Gray", "-depth", "8", "pgm:-"])
+        .output()
+        .await
+        .map_err(|e| format!("デコードコマンド実行エラー: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("画像のデコードに失敗しました: {}", path));
+    }
+    parse_pgm(&output.stdout).ok_or_else(|| format!("デコード結果を読み取れません: {}", path))
+}
+
+/// 画像をImageMagickで16bitのRGBにデコードする（グレースケールは3チャンネルに展開し、透明度は捨てる）
+pub async fn decode_rgb(path: &str) -> Result<RgbImage, String> {
+    let output = imagemagick::command()
+        .args([path, "-depth", "16", "ppm:-"])
+        .output()
+        .await
+        .map_err(|e| format!("デコードコマンド実行エラー: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("画像のデコードに失敗しました: {}", path));
+    }
+    parse_ppm(&output.stdout).ok_or_else(|| format!("デコード結果を読み取れません: {}", path))
+}
+
+/// バイナリ形式のPGM（`P5 幅 高さ 最大値` のヘッダーに画素が続く）を読み取る
+fn parse_pgm(data: &[u8]) -> Option<GrayImage> {
+    let (fields, pos) = parse_header(data)?;
+    if fields[0] != "P5" || fields[3] != "255" {
+        return None;
+    }
+    let width: usize = fields[1].parse().ok()?;
+    let height: usize = fields[2].parse().ok()?;
+    let pixels = data.get(pos..pos + width * height)?.to_vec();
+    Some(GrayImage { width, height, pixels })
+}
+
+/// 16bitのバイナリ形式のPPM（`P6 幅 高さ 65535` のヘッダーにビッグエンディアンの画素が続く）を読み取る
+fn parse_ppm(data: &[u8]) -> Option<RgbImage> {
+    let (fields, pos) = parse_header(data)?;
+    if fields[0] != "P6" || fields[3] != "65535" {
+        return None;
+    }
+    let width: usize = fields[1].parse().ok()?;
+    let height: usize = fields[2].parse().ok()?;
+    let pixels = data.get(pos..pos + width * height * 6)?
+        .chunks_exact(6)
+        .map(|pixel| {
+            let channel = |index: usize| u16::from_be_bytes([pixel[index], pixel[index + 1]]) as f32 / 65535.0;
+            [channel(0), channel(2), channel(4)]
+        })
+        .collect();
+    Some(RgbImage { width, height, pixels })
+}
+
+/// PNM形式のヘッダーの4つの値（形式・幅・高さ・最大値）と、画素の開始位置を読み取る
+fn parse_header(data: &[u8]) -> Option<(Vec<&str>, usize)> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while fields.len() < 4 {
+        while data.get(pos)?.is_ascii_whitespace() {
+            pos += 1;
+        }
+        // コメント行は読み飛ばす
+        if data[pos] == b'#' {
+            while data.get(pos)? != &b'\n' {
+                pos += 1;
+            }
+            continue;
+        }
+        let start = pos;
+        while !data.get(pos)?.is_ascii_whitespace() {
+            pos += 1;
+        }
+        fields.push(std::str::from_utf8(&data[start..pos]).ok()?);
+    }
+    // ヘッダーの直後の空白1文字から画素が始まる
+    Some((fields, pos + 1))
+}
+
+/// 8x8の窓を4画素ずつずらしながら求めたSSIMの平均（1.0で完全一致）
+///
+/// 画像サイズが異なる場合や、窓より小さい画像では求められないため `None` を返す。
+pub fn ssim(a: &GrayImage, b: &GrayImage) -> Option<f64> {
+    const WINDOW: usize = 8;
+    const STEP: usize = 4;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    if a.width != b.width || a.height != b.height || a.width < WINDOW || a.height < WINDOW {
+        return None;
+    }
+
+    let n = (WINDOW * WINDOW) as f64;
+    let mut total = 0.0;
+    let mut count = 0;
+    for y in (0..=a.height - WINDOW).step_by(STEP) {
+        for x in (0..=a.width - WINDOW).step_by(STEP) {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for dy in 0..WINDOW {
+                let row = (y + dy) * a.width + x;
+                for (&pa, &pb) in a.pixels[row..row + WINDOW].iter().zip(&b.pixels[row..row + WINDOW]) {
+                    let (pa, pb) = (pa as f64, pb as f64);
+                    sum_a += pa;
+                    sum_b += pb;
+                    sum_aa += pa * pa;
+                    sum_bb += pb * pb;
+                    sum_ab += pa * pb;
+                }
+            }
+
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            count += 1;
+        }
+    }
+
+    Some(total / count as f64)
+}
+
+/// SSIMULACRA2のスコア（100で完全一致。目安は90以上で見分けがつかない、70で高品質、50で中程度、30で低品質）
+///
+/// XYB色空間・6段階の解像度でSSIMと、ブロックノイズなどの加わった成分・ぼけて失われた成分を比べる知覚的な指標。
+/// 画像サイズが異なる場合や、8x8より小さい画像では求められないため `None` を返す。
+pub fn ssimulacra2(a: &RgbImage, b: &RgbImage) -> Option<f64> {
+    let frame = |image: &RgbImage| {
+        Rgb::new(image.pixels.clone(), image.width, image.height, TransferCharacteristic::SRGB, ColorPrimaries::BT709).ok()
+    };
+    ssimulacra2::compute_frame_ssimulacra2(frame(a)?, frame(b)?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(width: usize, height: usize, pixel: impl Fn(usize, usize) -> u8) -> GrayImage {
+        let pixels = (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).map(|(x, y)| pixel(x, y)).collect();
+        GrayImage { width, height, pixels }
+    }
+
+    #[test]
+    fn ssim_of_identical_images_is_one() {
+        let image = gray(16, 12, |x, y| (x * 13 + y * 7) as u8);
+        let copy = gray(16, 12, |x, y| (x * 13 + y * 7) as u8);
+        assert!((ssim(&image, &copy).unwrap() - 1.0).abs() < 1e-12);
+
+        let noisy = gray(16, 12, |x, y| (x * 13 + y * 7 + (x ^ y) % 5 * 10) as u8);
+        assert!(ssim(&image, &noisy).unwrap() < 1.0);
+    }
+
+    #[test]
+    fn ssim_requires_same_size_and_full_window() {
+        assert_eq!(ssim(&gray(16, 16, |_, _| 0), &gray(16, 12, |_, _| 0)), None);
+        assert_eq!(ssim(&gray(7, 16, |_, _| 0), &gray(7, 16, |_, _| 0)), None);
+        assert!(ssim(&gray(8, 8, |_, _| 0), &gray(8, 8, |_, _| 0)).is_some());
+    }
+
+    #[test]
+    fn parse_header_skips_comments() {
+        let data = b"P5\n# CREATOR: test\n3 2\n# depth\n1023\nXYZ";
+        let (fields, pos) = parse_header(data).unwrap();
+        assert_eq!(fields, ["P5", "3", "2", "1023"]);
+        assert_eq!(&data[pos..], b"XYZ");
+    }
+
+    #[test]
+    fn parse_pgm_reads_8bit_pixels() {
+        let image = parse_pgm(b"P5\n# comment\n3 2\n255\n\x00\x01\x02\x03\x04\xff").unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.pixels, [0, 1, 2, 3, 4, 255]);
+
+        // 8bit以外の最大値、画素の不足、別の形式は読み取らない
+        assert!(parse_pgm(b"P5 1 1 65535\n\x00\x01").is_none());
+        assert!(parse_pgm(b"P5 2 2 255\n\x00\x01\x02").is_none());
+        assert!(parse_pgm(b"P6 1 1 255\n\x00\x00\x00").is_none());
+    }
+
+    #[test]
+    fn parse_ppm_reads_16bit_pixels() {
+        let image = parse_ppm(b"P6\n# comment\n2 1\n65535\n\xff\xff\x00\x00\x80\x00\x00\x00\x00\x00\xff\xff").unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels[0], [1.0, 0.0, 32768.0 / 65535.0]);
+        assert_eq!(image.pixels[1], [0.0, 0.0, 1.0]);
+
+        assert!(parse_ppm(b"P6 1 1 255\n\x00\x00\x00").is_none());
+        assert!(parse_ppm(b"P6 1 1 65535\n\x00\x00").is_none());
+    }
+}
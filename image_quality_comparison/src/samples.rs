@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use tokio::io::AsyncWriteExt;
+use benchmark_common::imagemagick;
+
+use crate::formats::QualityFormat;
+use crate::metrics::{self, RgbImage};
+use crate::variant::ImageVariant;
+
+/// 熱マップで白になる差の大きさ（各チャンネル0〜1の値の差）
+///
+/// 品質ごとの熱マップを見比べられるよう、画像ごとに自動調整せず固定の尺度で色を付ける。
+const HEATMAP_FULL_SCALE: f32 = 0.25;
+
+/// 熱マップの色の段階（差なしの黒から、青・赤・黄を経て白）
+const HEATMAP_COLORS: &[[f32; 3]] = &[
+    [0.0, 0.0, 0.0],
+    [0.0, 0.0, 255.0],
+    [255.0, 0.0, 0.0],
+    [255.0, 255.0, 0.0],
+    [255.0, 255.0, 255.0],
+];
+
+/// `--keep-samples` の画像を残すディレクトリ（`<結果ファイル名>_samples/<実行ID>`）
+pub fn samples_root(results_file: &Path, run_id: &str) -> PathBuf {
+    let stem = results_file.file_stem().unwrap_or_default().to_string_lossy();
+    results_file.with_file_name(format!("{}_samples", stem)).join(run_id)
+}
+
+/// 寸法・色の種類・ビット深度の組み合わせごとのディレクトリ（例: `1024x1024_rgb_8bit`）
+pub fn variant_dir(samples_root: &Path, variant: &ImageVariant) -> PathBuf {
+    samples_root.join(variant.label().replace(' ', "_"))
+}
+
+/// 元画像の先頭 `count` 枚を `samples_dir` にコピーする
+pub fn keep_sources(output_dir: &str, samples_dir: &Path, count: u32) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(samples_dir)?;
+    for i in 0..count {
+        let file_name = format!("image_{:03}.png", i);
+        fs::copy(Path::new(output_dir).join(&file_name), samples_dir.join(&file_name))?;
+    }
+    Ok(())
+}
+
+/// 品質 `quality` で変換した先頭 `count` 枚と、元画像との差の熱マップを `samples_dir` にコピー・作成する
+///
+/// 熱マップは変換後のファイル名に `_diff.png` を付けた名前（例: `image_000_q80_diff.png`）で保存する。
+pub async fn keep_outputs(
+    output_dir: &str,
+    samples_dir: &Path,
+    format: &QualityFormat,
+    quality: u32,
+    count: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let samples_dir_name = samples_dir.to_string_lossy();
+    for i in 0..count {
+        let output_path = format.file_path(output_dir, i, quality);
+        let kept_path = format.file_path(&samples_dir_name, i, quality);
+        fs::copy(&output_path, &kept_path)?;
+
+        let source = metrics::decode_rgb(&format!("{}/image_{:03}.png", output_dir, i)).await?;
+        let decoded = metrics::decode_rgb(&output_path).await?;
+        let Some(heatmap) = heatmap_ppm(&source, &decoded) else {
+            eprintln!("警告: 画像サイズが元画像と異なるため、差の熱マップを作成できません: {}", output_path);
+            continue;
+        };
+        let diff_path = format!("{}_diff.png", Path::new(&kept_path).with_extension("").display());
+        write_png(&heatmap, &diff_path).await?;
+    }
+    Ok(())
+}
+
+/// 画素ごとにRGBで最も大きいチャンネルの差を色に置き換えた熱マップを、8bitのPPMとして返す
+///
+/// 画像サイズが異なる場合は画素を対応付けられないため `None` を返す。
+fn heatmap_ppm(source: &RgbImage, decoded: &RgbImage) -> Option<Vec<u8>> {
+    if source.width != decoded.width || source.height != decoded.height {
+        return None;
+    }
+
+    let mut data = format!("P6\n{} {}\n255\n", source.width, source.height).into_bytes();
+    for (a, b) in source.pixels.iter().zip(&decoded.pixels) {
+        let difference = (0..3).map(|channel| (a[channel] - b[channel]).abs()).fold(0.0, f32::max);
+        data.extend(heat_color(difference / HEATMAP_FULL_SCALE));
+    }
+    Some(data)
+}
+
+/// 0〜1の値 `t` を熱マップの色に変換する（1以上は白）
+fn heat_color(t: f32) -> [u8; 3] {
+    let position = t.clamp(0.0, 1.0) * (HEATMAP_COLORS.len() - 1) as f32;
+    let index = (position as usize).min(HEATMAP_COLORS.len() - 2);
+    let fraction = position - index as f32;
+    let (from, to) = (HEATMAP_COLORS[index], HEATMAP_COLORS[index + 1]);
+    [0, 1, 2].map(|channel| (from[channel] + (to[channel] - from[channel]) * fraction).round() as u8)
+}
+
+/// PPMの画像データをImageMagickに標準入力で渡し、PNGとして `path` に保存する
+async fn write_png(ppm: &[u8], path: &str) -> Result<(), String> {
+    let mut child = imagemagick::command()
+        .args(["ppm:-", path])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("熱マップの保存コマンド実行エラー: {}", e))?;
+
+    let mut stdin = child.stdin.take().ok_or("熱マップの保存コマンドに画像を渡せません")?;
+    stdin.write_all(ppm).await.map_err(|e| format!("熱マップの保存コマンドに画像を渡せません: {}", e))?;
+    drop(stdin);
+
+    let status = child.wait().await.map_err(|e| format!("熱マップの保存コマンド実行エラー: {}", e))?;
+    if !status.success() {
+        return Err(format!("熱マップの保存に失敗しました: {}", path));
+    }
+    Ok(())
+}
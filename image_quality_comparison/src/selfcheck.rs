@@ -0,0 +1,50 @@
+use std::fs;
+use std::hash::{DefaultHasher, Hasher};
+use std::process::Command;
+
+use benchmark_common::imagemagick;
+
+use crate::formats::QualityFormat;
+
+/// 品質 `quality` で変換した全画像の内容のハッシュ（画像番号順）
+///
+/// 品質の異なる出力が全画像でバイト単位に同じかどうかを比べるために使う。
+pub fn output_digest(output_dir: &str, format: &QualityFormat, quality: u32, count: u32) -> Result<Vec<u64>, Box<dyn std::error::Error>> {
+    let mut digest = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&fs::read(format.file_path(output_dir, i, quality))?);
+        digest.push(hasher.finish());
+    }
+    Ok(digest)
+}
+
+/// このフォーマットの変換に使われるImageMagickとデリゲート（ライブラリ）のバージョンを表す行
+///
+/// `convert -version` の `Version:` と `Delegates` の行、`convert -list format` のこのフォーマットの行
+/// （例: `WEBP* WEBP rw+ WebP Image Format (libwebp 1.2.4 [020F])`）を返す。取得できない行は含めない。
+pub fn delegate_report(format: &QualityFormat) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(version) = command_output(&["-version"]) {
+        lines.extend(
+            version.lines()
+                .map(str::trim)
+                .filter(|line| line.starts_with("Version:") || line.starts_with("Delegates"))
+                .map(str::to_string),
+        );
+    }
+    if let Some(list) = command_output(&["-list", "format"]) {
+        lines.extend(
+            list.lines()
+                .map(str::trim)
+                .filter(|line| line.split_whitespace().next().map(|name| name.trim_end_matches('*')) == Some(format.coder))
+                .map(str::to_string),
+        );
+    }
+    lines
+}
+
+fn command_output(args: &[&str]) -> Option<String> {
+    let output = Command::new(imagemagick::program()).args(args).output().ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
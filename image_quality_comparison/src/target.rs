@@ -0,0 +1,200 @@
+use std::fs;
+use std::sync::Arc;
+
+use crate::formats::{self, QualityFormat};
+use crate::metrics::{self, GrayImage};
+
+/// 品質の探索範囲（ImageMagick では品質0は未指定扱いになるため、最低品質から）
+const MIN_QUALITY: u32 = formats::MIN_QUALITY;
+const MAX_QUALITY: u32 = 100;
+
+/// `--target-size` / `--target-ssim` で指定する品質探索の目標
+#[derive(Clone, Copy)]
+pub enum QualityTarget {
+    /// ファイルサイズ（バイト）がこの値以下になる最も高い品質
+    Size(u64),
+    /// SSIMがこの値以上になる最も低い品質
+    Ssim(f64),
+}
+
+impl QualityTarget {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            QualityTarget::Size(_) => "size",
+            QualityTarget::Ssim(_) => "ssim",
+        }
+    }
+
+    pub fn value(&self) -> f64 {
+        match *self {
+            QualityTarget::Size(size) => size as f64,
+            QualityTarget::Ssim(score) => score,
+        }
+    }
+}
+
+/// 1枚の画像について探索した結果
+pub struct SearchResult {
+    pub quality: u32,
+    pub size: u64,
+    /// 目標がSSIMの場合のみ計算する
+    pub ssim: Option<f64>,
+    pub iterations: u32,
+    pub target_met: bool,
+}
+
+/// 品質を二分探索し、目標を満たす品質を探す
+///
+/// 品質を上げるほどサイズとSSIMが大きくなることを前提とする。どの品質でも目標を満たせない場合は
+/// 最も目標に近い端の品質（サイズなら最低品質、SSIMなら最高品質）の結果を `target_met: false` で返す。
+/// 探索中の画像は `output_path` に上書きしながら作成する。
+pub async fn search(source_path: &str, output_path: &str, format: &QualityFormat, target: QualityTarget) -> Result<SearchResult, String> {
+    let reference = match target {
+        QualityTarget::Ssim(_) => Some(Arc::new(metrics::decode_gray(source_path).await?)),
+        QualityTarget::Size(_) => None,
+    };
+
+    bisect(target, |quality| measure(source_path, output_path, format, quality, reference.as_ref())).await
+}
+
+/// `MIN_QUALITY` から `MAX_QUALITY` の品質を二分探索する（`measure` は品質からサイズとSSIMを求める）
+async fn bisect<F, Fut>(target: QualityTarget, mut measure: F) -> Result<SearchResult, String>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<(u64, Option<f64>), String>>,
+{
+    let (mut low, mut high) = (MIN_QUALITY, MAX_QUALITY);
+    let mut iterations = 0;
+    let mut best = None;
+    let mut last = None;
+
+    while low <= high {
+        let quality = (low + high) / 2;
+        let (size, ssim) = measure(quality).await?;
+        iterations += 1;
+
+        let met = match target {
+            QualityTarget::Size(max_size) => size <= max_size,
+            QualityTarget::Ssim(min_score) => ssim.is_some_and(|score| score >= min_score),
+        };
+
+        // サイズは満たしていればより高い品質を、SSIMは満たしていればより低い品質を試す
+        match (target, met) {
+            (QualityTarget::Size(_), true) | (QualityTarget::Ssim(_), false) => low = quality + 1,
+            _ => high = quality - 1,
+        }
+
+        if met {
+            best = Some((quality, size, ssim));
+        }
+        last = Some((quality, size, ssim));
+    }
+
+    let target_met = best.is_some();
+    let (quality, size, ssim) = best.or(last).expect("探索範囲が空でないため少なくとも1回は計測している");
+    Ok(SearchResult { quality, size, ssim, iterations, target_met })
+}
+
+/// 品質 `quality` で変換し、ファイルサイズと（基準画像がある場合は）SSIMを測る
+async fn measure(
+    source_path: &str,
+    output_path: &str,
+    format: &QualityFormat,
+    quality: u32,
+    reference: Option<&Arc<GrayImage>>,
+) -> Result<(u64, Option<f64>), String> {
+    let status = format.encode_command(source_path, output_path, quality)
+        .status()
+        .await
+        .map_err(|e| format!("変換コマンド実行エラー: {}", e))?;
+    if !status.success() {
+        return Err(format!("画像変換に失敗しました: {} -> {}", source_path, output_path));
+    }
+
+    let size = fs::metadata(output_path).map_err(|e| format!("{}: {}", output_path, e))?.len();
+    let ssim = match reference {
+        Some(reference) => {
+            let reference = Arc::clone(reference);
+            let distorted = metrics::decode_gray(output_path).await?;
+            // SSIMの計算はCPUを使い続けるため、非同期のワーカーを止めないよう別スレッドで行う
+            tokio::task::spawn_blocking(move || metrics::ssim(&reference, &distorted))
+                .await
+                .map_err(|e| format!("SSIMの計算に失敗しました: {}", e))?
+        },
+        None => None,
+    };
+    Ok((size, ssim))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 品質に比例してサイズとSSIMが大きくなる計測（訪れた品質を記録する）
+    async fn run(target: QualityTarget, visited: &mut Vec<u32>) -> SearchResult {
+        bisect(target, |quality| {
+            visited.push(quality);
+            async move { Ok((quality as u64 * 1000, Some(quality as f64 / 100.0))) }
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn size_target_finds_highest_quality_within_size() {
+        let result = run(QualityTarget::Size(50_500), &mut Vec::new()).await;
+        assert_eq!(result.quality, 50);
+        assert_eq!(result.size, 50_000);
+        assert!(result.target_met);
+        assert_eq!(result.ssim, Some(0.5));
+    }
+
+    #[tokio::test]
+    async fn ssim_target_finds_lowest_quality_reaching_score() {
+        let result = run(QualityTarget::Ssim(0.905), &mut Vec::new()).await;
+        assert_eq!(result.quality, 91);
+        assert!(result.target_met);
+    }
+
+    #[tokio::test]
+    async fn unreachable_targets_return_nearest_end() {
+        let result = run(QualityTarget::Size(999), &mut Vec::new()).await;
+        assert_eq!((result.quality, result.target_met), (MIN_QUALITY, false));
+
+        let result = run(QualityTarget::Ssim(1.5), &mut Vec::new()).await;
+        assert_eq!((result.quality, result.target_met), (MAX_QUALITY, false));
+
+        let result = bisect(QualityTarget::Ssim(0.5), |_| async { Ok((1000, None)) }).await.unwrap();
+        assert_eq!((result.quality, result.target_met), (MAX_QUALITY, false));
+    }
+
+    #[tokio::test]
+    async fn search_stays_in_range_and_terminates() {
+        for max_size in (0..=101_000).step_by(250) {
+            let mut visited = Vec::new();
+            let result = run(QualityTarget::Size(max_size), &mut visited).await;
+            let expected = (max_size / 1000).clamp(MIN_QUALITY as u64, MAX_QUALITY as u64) as u32;
+            assert_eq!(result.quality, expected);
+            assert!(result.iterations <= 7);
+            assert_eq!(result.iterations as usize, visited.len());
+            assert!(visited.iter().all(|quality| (MIN_QUALITY..=MAX_QUALITY).contains(quality)));
+        }
+
+        // 単調でない計測でも範囲内で終了する
+        let mut visited = Vec::new();
+        let result = bisect(QualityTarget::Size(500), |quality| {
+            visited.push(quality);
+            async move { Ok((if quality % 2 == 0 { 0 } else { 1000 }, None)) }
+        })
+        .await
+        .unwrap();
+        assert!((MIN_QUALITY..=MAX_QUALITY).contains(&result.quality));
+        assert!(visited.len() <= 7);
+    }
+
+    #[tokio::test]
+    async fn measure_error_stops_search() {
+        let result = bisect(QualityTarget::Size(1000), |_| async { Err("失敗".to_string()) }).await;
+        assert_eq!(result.err().as_deref(), Some("失敗"));
+    }
+}